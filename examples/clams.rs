@@ -1,10 +1,8 @@
 use blocknative::{
     models::Blockchain,
-    ws::{models::WatchConfig, ws::Ws},
+    ws::{abi, filter::Filter, models::WatchConfig, ws::Ws},
 };
 use futures_util::StreamExt;
-use std::collections::HashMap;
-use tokio::fs::read_to_string;
 
 #[tokio::main]
 pub async fn main() {
@@ -18,21 +16,14 @@ pub async fn main() {
     .await
     .unwrap();
 
-    let s = read_to_string("examples/quickswap.json").await.unwrap();
-    let abi = serde_json::from_str(&s).unwrap();
+    let abi = abi::from_file("examples/quickswap.json").unwrap();
 
-    let mut filters = HashMap::new();
-    filters.insert(
-        "contractCall.params.path".to_string(),
-        "0x4d6A30EFBE2e9D7A9C143Fce1C5Bb30d9312A465".to_string(),
-    );
-
-    let config = WatchConfig {
-        scope: "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff".to_string(),
-        filters: vec![filters],
-        abi,
-        watch_address: true,
-    };
+    let config = WatchConfig::new("0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff")
+        .with_abi(abi)
+        .filter(Filter::eq(
+            "contractCall.params.path",
+            "0x4d6A30EFBE2e9D7A9C143Fce1C5Bb30d9312A465",
+        ));
     tracing::info!(
         "Subscribing to filter on: {:?}",
         "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff"
@@ -42,6 +33,13 @@ pub async fn main() {
     tracing::info!("Waiting for events..");
 
     while let Some(response) = stream.next().await {
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::error!("Subscription ended: {}", e);
+                break;
+            }
+        };
         if let Some(event) = response.event {
             tracing::info!(
                 "I sense a disturbance in the force! {}, {}",
@@ -0,0 +1,311 @@
+//! Client for Blocknative's gas platform, the REST API which estimates gas
+//! prices for the next blocks.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), blocknative::gas::GasError> {
+//! use blocknative::{gas::GasClient, models::Blockchain};
+//!
+//! let gas = GasClient::new("api-key");
+//! let prices = gas.block_prices(&Blockchain::polygon()).await?;
+//! if let Some(estimate) = prices.estimate(90) {
+//!     let fees = estimate.fees();
+//!     println!("{} / {} wei", fees.max_fee_per_gas, fees.max_priority_fee_per_gas);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use crate::{
+    http::{self, ApiError, Http},
+    models::Blockchain,
+};
+
+/// Base url of the gas platform
+pub const URL: &str = http::URL;
+
+const GWEI: f64 = 1e9;
+
+/// Error thrown when requesting gas estimates
+#[derive(Error, Debug)]
+pub enum GasError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    /// The blockchain has no chain id the gas platform could look up
+    #[error("No gas estimates for {0:?}")]
+    UnsupportedChain(Blockchain),
+
+    /// The gas platform answered with an error status
+    #[error("Gas platform returned {status}: {message}")]
+    Api { status: u16, message: String },
+}
+
+impl From<ApiError> for GasError {
+    fn from(ApiError { status, message }: ApiError) -> Self {
+        GasError::Api { status, message }
+    }
+}
+
+/// Client of the gas platform.
+#[derive(Debug, Clone)]
+pub struct GasClient {
+    http: Http,
+    api_key: String,
+}
+
+impl GasClient {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_url(URL, api_key)
+    }
+
+    /// Talks to the gas platform at `url` instead, e.g. a local stand-in
+    pub fn with_url(url: impl Into<String>, api_key: &str) -> Self {
+        Self {
+            http: Http::new(url),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// Price estimates for the next blocks of `blockchain`, at the platform's
+    /// default confidence levels
+    pub async fn block_prices(&self, blockchain: &Blockchain) -> Result<BlockPrices, GasError> {
+        self.block_prices_with_confidence(blockchain, &[]).await
+    }
+
+    /// Price estimates for the next blocks of `blockchain`, at the given
+    /// confidence levels (1 to 99 percent)
+    pub async fn block_prices_with_confidence(
+        &self,
+        blockchain: &Blockchain,
+        confidence_levels: &[u8],
+    ) -> Result<BlockPrices, GasError> {
+        let chain_id = blockchain
+            .chain_id()
+            .ok_or_else(|| GasError::UnsupportedChain(blockchain.clone()))?;
+
+        let mut query = vec![("chainid", chain_id.to_string())];
+        if !confidence_levels.is_empty() {
+            let levels: Vec<String> = confidence_levels.iter().map(u8::to_string).collect();
+            query.push(("confidenceLevels", levels.join(",")));
+        }
+
+        let resp = self
+            .http
+            .client
+            .get(self.http.url("/gasprices/blockprices"))
+            .header(reqwest::header::AUTHORIZATION, &self.api_key)
+            .query(&query)
+            .send()
+            .await?;
+        Ok(http::check(resp).await?.json().await?)
+    }
+}
+
+/// Estimates returned by `GasClient::block_prices`. Prices are in gwei.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockPrices {
+    pub system: String,
+    pub network: String,
+    pub unit: String,
+    pub max_price: f64,
+    pub current_block_number: u64,
+    pub ms_since_last_block: u64,
+    /// Estimates for the next block first
+    pub block_prices: Vec<BlockPrice>,
+    /// Base fee predictions for the blocks after the pending one, nearest first
+    #[serde(default, deserialize_with = "base_fee_predictions")]
+    pub estimated_base_fees: Vec<BaseFeePrediction>,
+}
+
+impl BlockPrices {
+    /// Estimate for the next block with at least `confidence` percent chance
+    /// of inclusion, the cheapest if several qualify
+    pub fn estimate(&self, confidence: u8) -> Option<&EstimatedPrice> {
+        self.block_prices
+            .first()?
+            .estimated_prices
+            .iter()
+            .filter(|price| price.confidence >= confidence)
+            .min_by_key(|price| price.confidence)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockPrice {
+    pub block_number: u64,
+    pub estimated_transaction_count: u64,
+    pub base_fee_per_gas: f64,
+    pub estimated_prices: Vec<EstimatedPrice>,
+}
+
+/// Price which gets a transaction into the block with `confidence` percent
+/// probability
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EstimatedPrice {
+    pub confidence: u8,
+    /// Gas price for legacy transactions
+    pub price: f64,
+    pub max_priority_fee_per_gas: f64,
+    pub max_fee_per_gas: f64,
+}
+
+impl EstimatedPrice {
+    /// The EIP-1559 fees of the estimate, in wei
+    pub fn fees(&self) -> Eip1559Fees {
+        Eip1559Fees {
+            max_fee_per_gas: to_wei(self.max_fee_per_gas),
+            max_priority_fee_per_gas: to_wei(self.max_priority_fee_per_gas),
+        }
+    }
+
+    /// The legacy gas price of the estimate, in wei
+    pub fn gas_price(&self) -> u128 {
+        to_wei(self.price)
+    }
+}
+
+/// Fees of an EIP-1559 transaction, in wei
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Eip1559Fees {
+    pub max_fee_per_gas: u128,
+    pub max_priority_fee_per_gas: u128,
+}
+
+#[cfg(feature = "ethers")]
+impl Eip1559Fees {
+    /// Sets the fees of `tx`
+    pub fn apply(&self, tx: &mut ethers::types::Eip1559TransactionRequest) {
+        tx.max_fee_per_gas = Some(self.max_fee_per_gas.into());
+        tx.max_priority_fee_per_gas = Some(self.max_priority_fee_per_gas.into());
+    }
+}
+
+/// Predicted base fee of the block `blocks_ahead` after the pending one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseFeePrediction {
+    pub blocks_ahead: u32,
+    pub estimates: Vec<BaseFeeEstimate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseFeeEstimate {
+    pub confidence: u8,
+    pub base_fee: f64,
+}
+
+fn to_wei(gwei: f64) -> u128 {
+    (gwei * GWEI).round() as u128
+}
+
+/// The platform sends `[{"pending+1": [...]}, {"pending+2": [...]}, ...]`
+fn base_fee_predictions<'de, D>(deserializer: D) -> Result<Vec<BaseFeePrediction>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let blocks = Option::<Vec<BTreeMap<String, Vec<BaseFeeEstimate>>>>::deserialize(deserializer)?;
+    let mut predictions: Vec<BaseFeePrediction> = blocks
+        .unwrap_or_default()
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, estimates)| {
+            let blocks_ahead = key.strip_prefix("pending+")?.parse().ok()?;
+            Some(BaseFeePrediction {
+                blocks_ahead,
+                estimates,
+            })
+        })
+        .collect();
+    predictions.sort_by_key(|prediction| prediction.blocks_ahead);
+    Ok(predictions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Network, System},
+        testing::MockHttpServer,
+    };
+
+    const FIXTURE: &str = "tests/fixtures/blockprices.json";
+
+    #[test]
+    fn decodes_block_prices() {
+        let prices: BlockPrices =
+            serde_json::from_str(&std::fs::read_to_string(FIXTURE).unwrap()).unwrap();
+        assert_eq!(prices.current_block_number, 13005095);
+        assert_eq!(prices.block_prices[0].estimated_prices.len(), 5);
+
+        let estimate = prices.estimate(90).unwrap();
+        assert_eq!(estimate.confidence, 90);
+        assert_eq!(prices.estimate(91).unwrap().confidence, 95);
+        assert!(prices.estimate(100).is_none());
+        assert_eq!(
+            estimate.fees(),
+            Eip1559Fees {
+                max_fee_per_gas: 190_360_000_000,
+                max_priority_fee_per_gas: 1_080_000_000,
+            }
+        );
+
+        let blocks: Vec<u32> = prices
+            .estimated_base_fees
+            .iter()
+            .map(|prediction| prediction.blocks_ahead)
+            .collect();
+        assert_eq!(blocks, vec![1, 2, 3]);
+        assert_eq!(prices.estimated_base_fees[0].estimates[0].base_fee, 104.58);
+    }
+
+    #[tokio::test]
+    async fn requests_block_prices() {
+        let server = MockHttpServer::start().await.unwrap();
+        server.respond(200, std::fs::read_to_string(FIXTURE).unwrap());
+
+        let gas = GasClient::with_url(server.url(), "key");
+        let prices = gas
+            .block_prices_with_confidence(&Blockchain::polygon(), &[90, 99])
+            .await
+            .unwrap();
+        assert_eq!(prices.block_prices.len(), 1);
+
+        let requests = server.requests();
+        assert_eq!(
+            requests[0].target,
+            "/gasprices/blockprices?chainid=137&confidenceLevels=90%2C99"
+        );
+        assert_eq!(requests[0].header("authorization"), Some("key"));
+    }
+
+    #[tokio::test]
+    async fn surfaces_api_errors() {
+        let server = MockHttpServer::start().await.unwrap();
+        server.respond(401, r#"{"msg":"invalid api key"}"#);
+
+        let gas = GasClient::with_url(server.url(), "bad");
+        let err = gas.block_prices(&Blockchain::polygon()).await.unwrap_err();
+        assert!(
+            matches!(&err, GasError::Api { status: 401, message } if message.contains("invalid api key")),
+            "{:?}",
+            err
+        );
+
+        let bitcoin = Blockchain {
+            system: System::Bitcoin,
+            network: Network::Main,
+        };
+        assert!(matches!(
+            gas.block_prices(&bitcoin).await,
+            Err(GasError::UnsupportedChain(_))
+        ));
+        assert_eq!(server.requests().len(), 1);
+    }
+}
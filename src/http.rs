@@ -0,0 +1,45 @@
+//! Plumbing shared by the clients of Blocknative's HTTP APIs, `gas`, `rest`
+//! and `simulation`.
+
+/// Base url of every HTTP API
+pub(crate) const URL: &str = "https://api.blocknative.com";
+
+/// Connection to one of the HTTP APIs
+#[derive(Debug, Clone)]
+pub(crate) struct Http {
+    pub client: reqwest::Client,
+    base: String,
+}
+
+impl Http {
+    /// Talks to the API at `base`, with or without a trailing slash
+    pub fn new(base: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base: base.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Url of `path`, which starts with a slash
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+}
+
+/// Error status an API answered with, and the body explaining it
+pub(crate) struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+/// Hands back `resp` if its status is a success, its status and body otherwise
+pub(crate) async fn check(resp: reqwest::Response) -> Result<reqwest::Response, ApiError> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    Err(ApiError {
+        status: status.as_u16(),
+        message: resp.text().await.unwrap_or_default(),
+    })
+}
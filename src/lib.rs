@@ -1,5 +1,11 @@
 //! blocknative-rs
 //!
 //! Rust library for blocknative api
+pub mod gas;
+mod http;
 pub mod models;
+pub mod rest;
+pub mod simulation;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod ws;
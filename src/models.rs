@@ -9,6 +9,17 @@ pub enum System {
     Other(String),
 }
 
+impl System {
+    /// Name of the system in urls, e.g. `ethereum`
+    pub fn as_str(&self) -> &str {
+        match self {
+            System::Ethereum => "ethereum",
+            System::Bitcoin => "bitcoin",
+            System::Other(system) => system,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Network {
@@ -24,6 +35,22 @@ pub enum Network {
     Polygon,
 }
 
+impl Network {
+    /// Name of the network in urls, e.g. `matic-main`
+    pub fn as_str(&self) -> &str {
+        match self {
+            Network::Main => "main",
+            Network::Ropsten => "ropsten",
+            Network::Rinkeby => "rinkeby",
+            Network::Goerli => "goerli",
+            Network::Kovan => "kovan",
+            Network::XDai => "xdai",
+            Network::BSC => "bsc-main",
+            Network::Polygon => "matic-main",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Blockchain {
     pub system: System,
@@ -37,4 +64,22 @@ impl Blockchain {
             network: Network::Polygon,
         }
     }
+
+    /// EIP-155 chain id, `None` for non EVM systems
+    pub fn chain_id(&self) -> Option<u64> {
+        if self.system != System::Ethereum {
+            return None;
+        }
+
+        Some(match self.network {
+            Network::Main => 1,
+            Network::Ropsten => 3,
+            Network::Rinkeby => 4,
+            Network::Goerli => 5,
+            Network::Kovan => 42,
+            Network::XDai => 100,
+            Network::BSC => 56,
+            Network::Polygon => 137,
+        })
+    }
 }
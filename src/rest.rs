@@ -0,0 +1,317 @@
+//! Client for the account API, which manages the addresses and configs stored
+//! in the Blocknative account over HTTP.
+//!
+//! Addresses and configs stored in the account apply to every websocket
+//! connection of the api key, and the API uses the same `WatchConfig` and
+//! `Blockchain` types as `ws`:
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), blocknative::rest::RestError> {
+//! use blocknative::{models::Blockchain, rest::RestClient, ws::models::WatchConfig};
+//!
+//! let rest = RestClient::new("api-key");
+//! let polygon = Blockchain::polygon();
+//! rest.watch_address(&polygon, "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff").await?;
+//! rest.put_config(&polygon, &WatchConfig::new("0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff")).await?;
+//! for config in rest.configs(&polygon).await? {
+//!     println!("{} has {} filter(s)", config.scope, config.filters.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    http::{self, ApiError, Http},
+    models::{Blockchain, Network, System},
+    ws::{
+        abi::{self, AbiError},
+        filter::FilterError,
+        models::{AccountDescriptor, UnwatchRequest, WatchConfig, WatchRequest},
+    },
+};
+
+/// Base url of the account API
+pub const URL: &str = http::URL;
+
+/// Error thrown when managing the account
+#[derive(Error, Debug)]
+pub enum RestError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    /// The API answered with an error status
+    #[error("Account API returned {status}: {message}")]
+    Api { status: u16, message: String },
+
+    /// A config was not sent because its ABI is malformed
+    #[error(transparent)]
+    InvalidAbi(#[from] AbiError),
+
+    /// A config was not sent because its filters can never match
+    #[error(transparent)]
+    InvalidFilter(#[from] FilterError),
+}
+
+impl From<ApiError> for RestError {
+    fn from(ApiError { status, message }: ApiError) -> Self {
+        RestError::Api { status, message }
+    }
+}
+
+/// Body of every change, the account, network and the change itself
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Change<'a, T> {
+    api_key: &'a str,
+    blockchain: &'a System,
+    networks: [&'a Network; 1],
+    #[serde(flatten)]
+    params: T,
+}
+
+#[derive(Deserialize)]
+struct Items<T> {
+    items: Vec<T>,
+}
+
+/// Client of the account API.
+#[derive(Debug, Clone)]
+pub struct RestClient {
+    http: Http,
+    api_key: String,
+}
+
+impl RestClient {
+    pub fn new(api_key: &str) -> Self {
+        Self::with_url(URL, api_key)
+    }
+
+    /// Talks to the API at `url` instead, e.g. a local stand-in
+    pub fn with_url(url: impl Into<String>, api_key: &str) -> Self {
+        Self {
+            http: Http::new(url),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// Addresses the account watches on `blockchain`
+    pub async fn watched_addresses(
+        &self,
+        blockchain: &Blockchain,
+    ) -> Result<Vec<AccountDescriptor>, RestError> {
+        self.list("address", blockchain).await
+    }
+
+    /// Starts watching `address` on `blockchain`
+    pub async fn watch_address(
+        &self,
+        blockchain: &Blockchain,
+        address: &str,
+    ) -> Result<(), RestError> {
+        let account = AccountDescriptor {
+            address: address.to_string(),
+        };
+        self.change(reqwest::Method::POST, "address", blockchain, account)
+            .await
+    }
+
+    /// Stops watching `address` on `blockchain`
+    pub async fn unwatch_address(
+        &self,
+        blockchain: &Blockchain,
+        address: &str,
+    ) -> Result<(), RestError> {
+        let account = AccountDescriptor {
+            address: address.to_string(),
+        };
+        self.change(reqwest::Method::DELETE, "address", blockchain, account)
+            .await
+    }
+
+    /// Configs stored for `blockchain`
+    pub async fn configs(&self, blockchain: &Blockchain) -> Result<Vec<WatchConfig>, RestError> {
+        self.list("configs", blockchain).await
+    }
+
+    /// Stores `config`, replacing the one with the same scope. Fails without
+    /// sending it if its ABI is malformed or a filter can never match.
+    pub async fn put_config(
+        &self,
+        blockchain: &Blockchain,
+        config: &WatchConfig,
+    ) -> Result<(), RestError> {
+        abi::validate(&config.abi)?;
+        config.validate()?;
+
+        let request = WatchRequest {
+            config: config.clone(),
+        };
+        self.change(reqwest::Method::PUT, "configs", blockchain, request)
+            .await
+    }
+
+    /// Removes the config of `scope`
+    pub async fn delete_config(
+        &self,
+        blockchain: &Blockchain,
+        scope: &str,
+    ) -> Result<(), RestError> {
+        let request = UnwatchRequest::new(scope.to_string());
+        self.change(reqwest::Method::DELETE, "configs", blockchain, request)
+            .await
+    }
+
+    async fn list<T: DeserializeOwned>(
+        &self,
+        resource: &str,
+        blockchain: &Blockchain,
+    ) -> Result<Vec<T>, RestError> {
+        let url = self.http.url(&format!(
+            "/{}/{}/{}/{}",
+            resource,
+            self.api_key,
+            blockchain.system.as_str(),
+            blockchain.network.as_str()
+        ));
+        let resp = http::check(self.http.client.get(url).send().await?).await?;
+        Ok(resp.json::<Items<T>>().await?.items)
+    }
+
+    async fn change<T: Serialize>(
+        &self,
+        method: reqwest::Method,
+        resource: &str,
+        blockchain: &Blockchain,
+        params: T,
+    ) -> Result<(), RestError> {
+        let body = Change {
+            api_key: &self.api_key,
+            blockchain: &blockchain.system,
+            networks: [&blockchain.network],
+            params,
+        };
+        let resp = self
+            .http
+            .client
+            .request(method, self.http.url(&format!("/{}", resource)))
+            .json(&body)
+            .send()
+            .await?;
+        http::check(resp).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::MockHttpServer,
+        ws::{filter::Filter, models::TransactionStatus},
+    };
+    use serde_json::{json, Value};
+
+    const SCOPE: &str = "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff";
+
+    #[tokio::test]
+    async fn lists_account() {
+        let server = MockHttpServer::start().await.unwrap();
+        let rest = RestClient::with_url(server.url(), "key");
+
+        server.respond(
+            200,
+            json!({"items": [{"address": SCOPE, "network": "matic-main"}]}).to_string(),
+        );
+        let addresses = rest
+            .watched_addresses(&Blockchain::polygon())
+            .await
+            .unwrap();
+        assert_eq!(addresses[0].address, SCOPE);
+
+        let config = WatchConfig::new(SCOPE).filter(Filter::status(TransactionStatus::Pending));
+        server.respond(200, json!({ "items": [config] }).to_string());
+        let configs = rest.configs(&Blockchain::polygon()).await.unwrap();
+        assert_eq!(configs, vec![config]);
+
+        let other = Blockchain {
+            system: System::Other("tron".to_string()),
+            network: Network::Main,
+        };
+        rest.configs(&other).await.unwrap();
+
+        let targets: Vec<_> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.target)
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                "/address/key/ethereum/matic-main",
+                "/configs/key/ethereum/matic-main",
+                "/configs/key/tron/main"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_account() {
+        let server = MockHttpServer::start().await.unwrap();
+        server.respond(200, "{}");
+        let rest = RestClient::with_url(server.url(), "key");
+        let polygon = Blockchain::polygon();
+
+        rest.watch_address(&polygon, SCOPE).await.unwrap();
+        rest.put_config(&polygon, &WatchConfig::new(SCOPE))
+            .await
+            .unwrap();
+        rest.delete_config(&polygon, SCOPE).await.unwrap();
+
+        let requests = server.requests();
+        let bodies: Vec<Value> = requests
+            .iter()
+            .map(|request| serde_json::from_str(&request.body).unwrap())
+            .collect();
+        assert_eq!(
+            (requests[0].method.as_str(), requests[0].target.as_str()),
+            ("POST", "/address")
+        );
+        assert_eq!(
+            bodies[0],
+            json!({"apiKey": "key", "blockchain": "ethereum", "networks": ["matic-main"], "address": SCOPE})
+        );
+        assert_eq!(
+            (requests[1].method.as_str(), requests[1].target.as_str()),
+            ("PUT", "/configs")
+        );
+        assert_eq!(bodies[1]["config"]["scope"], SCOPE);
+        assert_eq!(requests[2].method, "DELETE");
+        assert_eq!(bodies[2]["config"], json!({ "scope": SCOPE }));
+    }
+
+    #[tokio::test]
+    async fn surfaces_api_errors() {
+        let server = MockHttpServer::start().await.unwrap();
+        server.respond(403, "address limit reached");
+        let rest = RestClient::with_url(server.url(), "key");
+
+        let err = rest
+            .watch_address(&Blockchain::polygon(), SCOPE)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, RestError::Api { status: 403, message } if message == "address limit reached"),
+            "{:?}",
+            err
+        );
+
+        let invalid = WatchConfig::new(SCOPE).filter(Filter::eq("status", "mined"));
+        assert!(matches!(
+            rest.put_config(&Blockchain::polygon(), &invalid).await,
+            Err(RestError::InvalidFilter(_))
+        ));
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,373 @@
+//! Client for Blocknative's simulation platform, which executes unsigned
+//! transactions on top of the latest block and reports what they would do.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), blocknative::simulation::SimulationError> {
+//! use blocknative::{
+//!     models::Blockchain,
+//!     simulation::{SimulationClient, UnsignedTransaction},
+//! };
+//!
+//! let sim = SimulationClient::new("api-key", "secret-key");
+//! let tx = UnsignedTransaction {
+//!     from: "0x21F3bB63e775ccDf0CC04559Be142971D241aB0E".to_string(),
+//!     to: "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff".to_string(),
+//!     gas: 400_000,
+//!     input: "0x38ed1739...".to_string(),
+//!     ..Default::default()
+//! };
+//! let simulation = sim.simulate(&Blockchain::polygon(), &tx).await?;
+//! for change in simulation.net_balance_changes {
+//!     println!("{}: {:?}", change.address, change.balance_changes);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::{
+    http::{self, ApiError, Http},
+    models::Blockchain,
+    ws::models::{ContractCall, InternalTransaction, NetBalanceChange},
+};
+
+/// Base url of the simulation platform
+pub const URL: &str = http::URL;
+
+/// Error thrown when simulating transactions
+#[derive(Error, Debug)]
+pub enum SimulationError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    /// The simulation platform answered with an error status
+    #[error("Simulation platform returned {status}: {message}")]
+    Api { status: u16, message: String },
+
+    /// A bundle needs at least one transaction
+    #[error("Empty bundle")]
+    EmptyBundle,
+
+    /// A field of a bundle reply does not hold one entry per transaction
+    #[error("Bundle reply has {len} `{field}` entries for {expected} transactions")]
+    BundleMismatch {
+        field: &'static str,
+        len: usize,
+        expected: usize,
+    },
+}
+
+impl From<ApiError> for SimulationError {
+    fn from(ApiError { status, message }: ApiError) -> Self {
+        SimulationError::Api { status, message }
+    }
+}
+
+/// Transaction to simulate. Amounts are in wei, set either `gas_price` or the
+/// EIP-1559 fees.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsignedTransaction {
+    pub from: String,
+    pub to: String,
+    pub gas: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_price: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fee_per_gas: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_priority_fee_per_gas: Option<u128>,
+    pub value: u128,
+    pub input: String,
+}
+
+/// Body of a simulation, the transaction and the chain to run it on
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    blockchain: &'a Blockchain,
+    #[serde(flatten)]
+    transaction: &'a UnsignedTransaction,
+}
+
+/// What a transaction did when simulated
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Simulation {
+    /// Block the transaction was simulated on top of
+    pub simulated_block_number: u64,
+    pub gas_used: u64,
+    #[serde(default)]
+    pub internal_transactions: Vec<InternalTransaction>,
+    #[serde(default)]
+    pub net_balance_changes: Vec<NetBalanceChange>,
+    /// The decoded call, if the platform knows the contract's ABI
+    pub contract_call: Option<ContractCall>,
+    /// Why the transaction failed, `None` if it succeeded
+    pub error: Option<Value>,
+}
+
+/// Reply to a bundle, which holds one entry per transaction in each field.
+/// Fields may be missing or `null` when no transaction has anything to report.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleSimulation {
+    simulated_block_number: u64,
+    gas_used: Vec<u64>,
+    internal_transactions: Option<Vec<Vec<InternalTransaction>>>,
+    net_balance_changes: Option<Vec<Vec<NetBalanceChange>>>,
+    contract_call: Option<Vec<Option<ContractCall>>>,
+    error: Option<Vec<Option<Value>>>,
+}
+
+impl BundleSimulation {
+    fn into_simulations(self) -> Result<Vec<Simulation>, SimulationError> {
+        let expected = self.gas_used.len();
+        let mut internal_transactions =
+            per_transaction("internalTransactions", self.internal_transactions, expected)?;
+        let mut net_balance_changes =
+            per_transaction("netBalanceChanges", self.net_balance_changes, expected)?;
+        let mut contract_calls = per_transaction("contractCall", self.contract_call, expected)?;
+        let mut errors = per_transaction("error", self.error, expected)?;
+        Ok(self
+            .gas_used
+            .into_iter()
+            .map(|gas_used| Simulation {
+                simulated_block_number: self.simulated_block_number,
+                gas_used,
+                internal_transactions: internal_transactions.next().unwrap_or_default(),
+                net_balance_changes: net_balance_changes.next().unwrap_or_default(),
+                contract_call: contract_calls.next().flatten(),
+                error: errors.next().flatten(),
+            })
+            .collect())
+    }
+}
+
+/// Entries of a bundle field, which must be absent or hold one per transaction
+fn per_transaction<T: Default>(
+    field: &'static str,
+    entries: Option<Vec<T>>,
+    expected: usize,
+) -> Result<impl Iterator<Item = T>, SimulationError> {
+    let entries = match entries {
+        Some(entries) if entries.len() != expected => {
+            return Err(SimulationError::BundleMismatch {
+                field,
+                len: entries.len(),
+                expected,
+            })
+        }
+        Some(entries) => entries,
+        None => std::iter::repeat_with(T::default).take(expected).collect(),
+    };
+    Ok(entries.into_iter())
+}
+
+/// Client of the simulation platform.
+#[derive(Debug, Clone)]
+pub struct SimulationClient {
+    http: Http,
+    credentials: String,
+}
+
+impl SimulationClient {
+    /// The platform needs the secret key of the account besides the api key
+    pub fn new(api_key: &str, secret_key: &str) -> Self {
+        Self::with_url(URL, api_key, secret_key)
+    }
+
+    /// Talks to the simulation platform at `url` instead, e.g. a local stand-in
+    pub fn with_url(url: impl Into<String>, api_key: &str, secret_key: &str) -> Self {
+        Self {
+            http: Http::new(url),
+            credentials: format!("{}:{}", api_key, secret_key),
+        }
+    }
+
+    /// Simulates `transaction` on `blockchain`
+    pub async fn simulate(
+        &self,
+        blockchain: &Blockchain,
+        transaction: &UnsignedTransaction,
+    ) -> Result<Simulation, SimulationError> {
+        let payload = Payload {
+            blockchain,
+            transaction,
+        };
+        self.post(&payload).await
+    }
+
+    /// Simulates `transactions` in order, each on top of the previous ones,
+    /// like a bundle which lands in one block
+    pub async fn simulate_bundle(
+        &self,
+        blockchain: &Blockchain,
+        transactions: &[UnsignedTransaction],
+    ) -> Result<Vec<Simulation>, SimulationError> {
+        if transactions.is_empty() {
+            return Err(SimulationError::EmptyBundle);
+        }
+        let payload: Vec<Payload> = transactions
+            .iter()
+            .map(|transaction| Payload {
+                blockchain,
+                transaction,
+            })
+            .collect();
+        let bundle: BundleSimulation = self.post(&payload).await?;
+        bundle.into_simulations()
+    }
+
+    async fn post<T, R>(&self, payload: &T) -> Result<R, SimulationError>
+    where
+        T: Serialize + ?Sized,
+        R: serde::de::DeserializeOwned,
+    {
+        let resp = self
+            .http
+            .client
+            .post(self.http.url("/simulate"))
+            .header("credentials", &self.credentials)
+            .json(payload)
+            .send()
+            .await?;
+        Ok(http::check(resp).await?.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockHttpServer;
+    use serde_json::json;
+
+    fn swap() -> UnsignedTransaction {
+        UnsignedTransaction {
+            from: "0x21F3bB63e775ccDf0CC04559Be142971D241aB0E".to_string(),
+            to: "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff".to_string(),
+            gas: 387473,
+            max_fee_per_gas: Some(200_000_000_000),
+            max_priority_fee_per_gas: Some(30_000_000_000),
+            input: "0x38ed1739".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn simulates_transaction() {
+        let server = MockHttpServer::start().await.unwrap();
+        server.respond(
+            200,
+            std::fs::read_to_string("tests/fixtures/simulation.json").unwrap(),
+        );
+        let sim = SimulationClient::with_url(server.url(), "key", "secret");
+
+        let simulation = sim.simulate(&Blockchain::polygon(), &swap()).await.unwrap();
+        assert_eq!(simulation.gas_used, 236672);
+        assert!(simulation.error.is_none());
+        assert!(matches!(
+            simulation.internal_transactions[..],
+            [
+                InternalTransaction::Call(_),
+                InternalTransaction::StaticCall(_)
+            ]
+        ));
+        let change = &simulation.net_balance_changes[0].balance_changes[0];
+        assert_eq!(change.delta, "-5000000000");
+        assert_eq!(change.asset.symbol, "USDC");
+        assert_eq!(change.asset.decimals, Some(6));
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            (request.method.as_str(), request.target.as_str()),
+            ("POST", "/simulate")
+        );
+        assert_eq!(request.header("credentials"), Some("key:secret"));
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["system"], "ethereum");
+        assert_eq!(body["network"], "matic-main");
+        assert_eq!(body["maxFeePerGas"], 200_000_000_000u64);
+        assert!(body.get("gasPrice").is_none());
+    }
+
+    #[tokio::test]
+    async fn simulates_bundle() {
+        let server = MockHttpServer::start().await.unwrap();
+        server.respond(
+            200,
+            json!({
+                "simulatedBlockNumber": 22235980,
+                "gasUsed": [21000, 120000],
+                "internalTransactions": [[], []],
+                "netBalanceChanges": [[], []],
+                "error": [null, "execution reverted"]
+            })
+            .to_string(),
+        );
+        let sim = SimulationClient::with_url(server.url(), "key", "secret");
+
+        let simulations = sim
+            .simulate_bundle(&Blockchain::polygon(), &[swap(), swap()])
+            .await
+            .unwrap();
+        assert_eq!(simulations.len(), 2);
+        assert_eq!(simulations[1].gas_used, 120000);
+        assert!(simulations[0].error.is_none());
+        assert_eq!(simulations[1].error, Some(json!("execution reverted")));
+
+        let body: Value = serde_json::from_str(&server.requests()[0].body).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        assert!(matches!(
+            sim.simulate_bundle(&Blockchain::polygon(), &[]).await,
+            Err(SimulationError::EmptyBundle)
+        ));
+    }
+
+    #[tokio::test]
+    async fn checks_bundle_reply_lengths() {
+        let server = MockHttpServer::start().await.unwrap();
+        let sim = SimulationClient::with_url(server.url(), "key", "secret");
+        let bundle = [swap(), swap()];
+
+        server.respond(
+            200,
+            json!({
+                "simulatedBlockNumber": 22235980,
+                "gasUsed": [21000, 120000],
+                "netBalanceChanges": null,
+                "error": null
+            })
+            .to_string(),
+        );
+        let simulations = sim
+            .simulate_bundle(&Blockchain::polygon(), &bundle)
+            .await
+            .unwrap();
+        assert!(simulations
+            .iter()
+            .all(|simulation| simulation.error.is_none()
+                && simulation.net_balance_changes.is_empty()));
+
+        server.respond(
+            200,
+            json!({
+                "simulatedBlockNumber": 22235980,
+                "gasUsed": [21000, 120000],
+                "internalTransactions": [[]]
+            })
+            .to_string(),
+        );
+        assert!(matches!(
+            sim.simulate_bundle(&Blockchain::polygon(), &bundle).await,
+            Err(SimulationError::BundleMismatch {
+                field: "internalTransactions",
+                len: 1,
+                expected: 2
+            })
+        ));
+    }
+}
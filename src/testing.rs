@@ -0,0 +1,464 @@
+//! Local websocket server speaking enough of the Blocknative protocol to run
+//! `Ws` without network access.
+//!
+//! The server greets every connection with a hello message, acknowledges each
+//! request by echoing it back and records it for later inspection. Tests
+//! script the rest: events played from JSON fixtures, rejected requests and
+//! dropped connections.
+//!
+//! `MockHttpServer` stands in for the REST APIs of `gas` and `rest` in the same
+//! way.
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Map, Value};
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Notify},
+};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::debug;
+
+use crate::ws::models::{CategoryCode, EventCode};
+
+const SERVER_VERSION: &str = "0.0.0-mock";
+
+/// Instructions for the live connections
+#[derive(Debug, Clone)]
+enum Command {
+    Send(String),
+    /// Stop reading, so pings go unanswered, but keep the socket open
+    Freeze,
+    Disconnect,
+}
+
+/// A scripted error reply for the next matching request
+#[derive(Debug)]
+struct Rejection {
+    category: CategoryCode,
+    event: EventCode,
+    reason: String,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Every request received, over all connections
+    received: Vec<Value>,
+    rejections: Vec<Rejection>,
+    /// Reason every request is refused with, like an invalid api key
+    api_key_rejection: Option<String>,
+    connections: Vec<mpsc::UnboundedSender<Command>>,
+}
+
+/// A mock Blocknative websocket server listening on localhost.
+///
+/// The server runs until it is dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    received: Arc<Notify>,
+    accepted: Arc<AtomicUsize>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Binds to a free local port and starts accepting connections
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let received = Arc::new(Notify::new());
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            let received = received.clone();
+            let accepted = accepted.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let id = accepted.fetch_add(1, Ordering::SeqCst);
+                    let (sender, commands) = mpsc::unbounded_channel();
+                    state.lock().unwrap().connections.push(sender);
+                    tokio::spawn(serve(
+                        stream,
+                        format!("mock-{}", id),
+                        commands,
+                        state.clone(),
+                        received.clone(),
+                    ));
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            state,
+            received,
+            accepted,
+            task,
+        })
+    }
+
+    /// The url to hand to `Ws::connect`
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Number of connections accepted so far
+    pub fn connections(&self) -> usize {
+        self.accepted.load(Ordering::SeqCst)
+    }
+
+    /// Sends a message to every open connection
+    pub fn send(&self, msg: impl Into<String>) {
+        self.command(Command::Send(msg.into()));
+    }
+
+    /// Sends each event of a JSON fixture, which holds either one event or an
+    /// array of them, to every open connection
+    pub fn play(&self, fixture: impl AsRef<Path>) -> std::io::Result<()> {
+        let text = std::fs::read_to_string(fixture)?;
+        let events = match serde_json::from_str(&text)? {
+            Value::Array(events) => events,
+            event => vec![event],
+        };
+        for event in events {
+            self.send(event.to_string());
+        }
+        Ok(())
+    }
+
+    /// Closes every open connection without a close frame
+    pub fn disconnect(&self) {
+        self.command(Command::Disconnect);
+    }
+
+    /// Stops serving every open connection without closing it, like a server
+    /// which hung or a network which silently dropped the connection
+    pub fn freeze(&self) {
+        self.command(Command::Freeze);
+    }
+
+    /// Answers the next `category`/`event` request with an error carrying `reason`
+    pub fn reject(&self, category: CategoryCode, event: EventCode, reason: impl Into<String>) {
+        self.state.lock().unwrap().rejections.push(Rejection {
+            category,
+            event,
+            reason: reason.into(),
+        });
+    }
+
+    /// Refuses every request like the server does for an unknown api key
+    pub fn reject_api_key(&self, reason: impl Into<String>) {
+        self.state.lock().unwrap().api_key_rejection = Some(reason.into());
+    }
+
+    /// Every request received so far
+    pub fn received(&self) -> Vec<Value> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Waits until `count` requests with the given codes have been received,
+    /// returns the last of them
+    pub async fn wait_for(&self, category: CategoryCode, event: EventCode, count: usize) -> Value {
+        loop {
+            let notified = self.received.notified();
+            let matching: Vec<Value> = self
+                .received()
+                .into_iter()
+                .filter(|request| is_request(request, &category, &event))
+                .collect();
+            if matching.len() >= count {
+                return matching[count - 1].clone();
+            }
+            notified.await;
+        }
+    }
+
+    fn command(&self, command: Command) {
+        self.state
+            .lock()
+            .unwrap()
+            .connections
+            .retain(|connection| connection.send(command.clone()).is_ok());
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.command(Command::Disconnect);
+    }
+}
+
+fn is_request(request: &Value, category: &CategoryCode, event: &EventCode) -> bool {
+    request["categoryCode"] == category.as_str() && request["eventCode"] == event.as_str()
+}
+
+async fn serve(
+    stream: TcpStream,
+    connection_id: String,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    state: Arc<Mutex<State>>,
+    received: Arc<Notify>,
+) {
+    let mut ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            debug!("mock handshake failed: {}", e);
+            return;
+        }
+    };
+
+    let hello = json!({
+        "version": 0,
+        "serverVersion": SERVER_VERSION,
+        "status": "ok",
+        "showUX": false,
+        "connectionId": connection_id,
+    });
+    if ws.send(Message::Text(hello.to_string())).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Send(msg)) => {
+                    if ws.send(Message::Text(msg)).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Command::Freeze) => {
+                    while let Some(command) = commands.recv().await {
+                        if let Command::Disconnect = command {
+                            return;
+                        }
+                    }
+                    return;
+                }
+                Some(Command::Disconnect) | None => return,
+            },
+            msg = ws.next() => match msg {
+                Some(Ok(Message::Text(text))) => {
+                    let request: Value = match serde_json::from_str(&text) {
+                        Ok(request) => request,
+                        Err(e) => {
+                            debug!("mock received invalid json: {}", e);
+                            continue;
+                        }
+                    };
+                    let reply = reply(&state, &connection_id, &request);
+                    state.lock().unwrap().received.push(request);
+                    received.notify_waiters();
+                    if ws.send(Message::Text(reply.to_string())).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(Message::Ping(data))) => {
+                    if ws.send(Message::Pong(data)).await.is_err() {
+                        return;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Builds the server's answer to `request`, which echoes the request back
+fn reply(state: &Mutex<State>, connection_id: &str, request: &Value) -> Value {
+    let mut state = state.lock().unwrap();
+    let mut reply = json!({
+        "version": 0,
+        "serverVersion": SERVER_VERSION,
+        "timeStamp": request["timeStamp"],
+        "connectionId": connection_id,
+        "status": "ok",
+    });
+
+    if let Some(reason) = &state.api_key_rejection {
+        reply["status"] = json!("error");
+        reply["reason"] = json!(reason);
+        return reply;
+    }
+
+    let mut event = Map::new();
+    for field in [
+        "timeStamp",
+        "dappId",
+        "blockchain",
+        "categoryCode",
+        "eventCode",
+        "config",
+        "transaction",
+        "account",
+    ] {
+        if let Some(value) = request.get(field) {
+            event.insert(field.to_string(), value.clone());
+        }
+    }
+    reply["event"] = Value::Object(event);
+
+    let rejection = state
+        .rejections
+        .iter()
+        .position(|rejection| is_request(request, &rejection.category, &rejection.event));
+    if let Some(index) = rejection {
+        let rejection = state.rejections.remove(index);
+        reply["status"] = json!("error");
+        reply["reason"] = json!(rejection.reason);
+    }
+    reply
+}
+
+/// A request received by `MockHttpServer`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// Path and query
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    /// Value of the header `name`, case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct HttpState {
+    status: u16,
+    body: String,
+    requests: Vec<HttpRequest>,
+}
+
+/// A mock HTTP API listening on localhost, which answers every request with
+/// the same response.
+///
+/// The server runs until it is dropped.
+#[derive(Debug)]
+pub struct MockHttpServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<HttpState>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl MockHttpServer {
+    /// Binds to a free local port and answers 404 until told otherwise
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(HttpState {
+            status: 404,
+            body: String::new(),
+            requests: Vec::new(),
+        }));
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve_http(stream, state.clone()));
+                }
+            }
+        });
+
+        Ok(Self { addr, state, task })
+    }
+
+    /// The url to hand to `GasClient::with_url` or `RestClient::with_url`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Answers every following request with `status` and the JSON `body`
+    pub fn respond(&self, status: u16, body: impl Into<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.status = status;
+        state.body = body.into();
+    }
+
+    /// Every request received so far
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockHttpServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers a single HTTP/1.1 request and closes the connection
+async fn serve_http(mut stream: TcpStream, state: Arc<Mutex<HttpState>>) {
+    let mut data = Vec::new();
+    let mut buf = [0; 1024];
+    let end = loop {
+        if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&data[..end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let target = request_line.next().unwrap_or_default().to_string();
+    let mut request = HttpRequest {
+        method,
+        target,
+        headers: lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .collect(),
+        body: String::new(),
+    };
+
+    let length: usize = request
+        .header("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or_default();
+    let mut body = data.split_off(end + 4);
+    while body.len() < length {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => body.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    request.body = String::from_utf8_lossy(&body).into_owned();
+
+    let (status, body) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request);
+        (state.status, state.body.clone())
+    };
+    let response = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
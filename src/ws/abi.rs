@@ -0,0 +1,265 @@
+//! Loading and validation of the JSON ABI sent with a `WatchConfig`.
+//!
+//! The server decodes contract calls of a config with its ABI, so a malformed
+//! ABI only shows up as events without `contractCall`. The loaders here accept
+//! plain ABI files as well as Hardhat and Foundry artifacts, and check every
+//! entry before it is used:
+//!
+//! ```no_run
+//! use blocknative::ws::{abi, models::WatchConfig};
+//!
+//! let abi = abi::from_file("artifacts/Router.json").unwrap();
+//! let config = WatchConfig::new("0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff").with_abi(abi);
+//! ```
+//!
+//! With the `ethers` feature an ABI can also be built from an ethers `Abi` or
+//! from human-readable signatures, and contract calls decode into typed values.
+use serde_json::Value;
+use std::path::Path;
+use thiserror::Error;
+
+#[cfg(feature = "ethers")]
+mod decode;
+#[cfg(feature = "ethers")]
+pub use decode::DecodeError;
+
+const ENTRY_TYPES: [&str; 6] = [
+    "function",
+    "constructor",
+    "event",
+    "fallback",
+    "receive",
+    "error",
+];
+
+/// Error thrown when an ABI cannot be loaded or is malformed
+#[derive(Error, Debug)]
+pub enum AbiError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    /// The JSON is neither an ABI nor an artifact with an `abi` field
+    #[error("expected an abi array or an artifact with an `abi` field")]
+    NotAnAbi,
+
+    #[error("invalid abi entry {index}: {reason}")]
+    InvalidEntry { index: usize, reason: String },
+
+    #[cfg(feature = "ethers")]
+    #[error("invalid signature: {0}")]
+    InvalidSignature(String),
+}
+
+/// Reads an ABI or artifact file, see `from_json`
+pub fn from_file(path: impl AsRef<Path>) -> Result<Vec<Value>, AbiError> {
+    from_json(&std::fs::read_to_string(path)?)
+}
+
+/// Parses a JSON ABI, or extracts it from a Hardhat or Foundry artifact
+pub fn from_json(json: &str) -> Result<Vec<Value>, AbiError> {
+    from_value(serde_json::from_str(json)?)
+}
+
+/// Like `from_json`, for JSON which is already parsed
+pub fn from_value(value: Value) -> Result<Vec<Value>, AbiError> {
+    let abi = match value {
+        Value::Array(abi) => abi,
+        Value::Object(mut artifact) => match artifact.remove("abi") {
+            Some(Value::Array(abi)) => abi,
+            _ => return Err(AbiError::NotAnAbi),
+        },
+        _ => return Err(AbiError::NotAnAbi),
+    };
+    validate(&abi)?;
+    Ok(abi)
+}
+
+/// Converts an ethers `Abi`, e.g. one generated by `abigen!`
+#[cfg(feature = "ethers")]
+pub fn from_ethers(abi: &ethers::abi::Abi) -> Result<Vec<Value>, AbiError> {
+    from_value(serde_json::to_value(abi)?)
+}
+
+/// Builds an ABI from human-readable signatures such as
+/// `function swap(uint256 amountIn, address[] path)`. Params need names for
+/// filters on `contractCall.params` to refer to them.
+#[cfg(feature = "ethers")]
+pub fn from_signatures(signatures: &[&str]) -> Result<Vec<Value>, AbiError> {
+    let abi = ethers::abi::parse_abi(signatures)
+        .map_err(|e| AbiError::InvalidSignature(e.to_string()))?;
+    from_ethers(&abi)
+}
+
+/// Checks that every entry of `abi` is a well formed function, event,
+/// constructor, fallback, receive or error description
+pub fn validate(abi: &[Value]) -> Result<(), AbiError> {
+    abi.iter().enumerate().try_for_each(|(index, entry)| {
+        validate_entry(entry).map_err(|reason| AbiError::InvalidEntry { index, reason })
+    })
+}
+
+fn validate_entry(entry: &Value) -> Result<(), String> {
+    if !entry.is_object() {
+        return Err("not an object".to_string());
+    }
+
+    // entries without a type are functions
+    let kind = match &entry["type"] {
+        Value::Null => "function",
+        Value::String(kind) if ENTRY_TYPES.contains(&kind.as_str()) => kind,
+        other => return Err(format!("unknown type {}", other)),
+    };
+
+    if matches!(kind, "function" | "event" | "error") {
+        match entry["name"].as_str() {
+            Some(name) if !name.is_empty() => {}
+            _ => return Err(format!("{} without a name", kind)),
+        }
+    }
+
+    for field in ["inputs", "outputs"] {
+        match &entry[field] {
+            Value::Null => {}
+            Value::Array(params) => params.iter().try_for_each(validate_param)?,
+            _ => return Err(format!("`{}` is not an array", field)),
+        }
+    }
+    Ok(())
+}
+
+fn validate_param(param: &Value) -> Result<(), String> {
+    let kind = param["type"]
+        .as_str()
+        .ok_or_else(|| format!("param without a type: {}", param))?;
+    let base = kind.split('[').next().unwrap_or_default();
+
+    if !is_array_suffix(&kind[base.len()..]) || !is_elementary(base) && base != "tuple" {
+        return Err(format!("invalid type `{}`", kind));
+    }
+    if base == "tuple" {
+        match &param["components"] {
+            Value::Array(components) => components.iter().try_for_each(validate_param)?,
+            _ => return Err(format!("`{}` without components", kind)),
+        }
+    }
+    Ok(())
+}
+
+/// `[]`, `[2]`, `[][3]`, ...
+fn is_array_suffix(suffix: &str) -> bool {
+    let mut rest = suffix;
+    while let Some(dims) = rest.strip_prefix('[') {
+        let end = match dims.find(']') {
+            Some(end) => end,
+            None => return false,
+        };
+        if !dims[..end].bytes().all(|b| b.is_ascii_digit()) {
+            return false;
+        }
+        rest = &dims[end + 1..];
+    }
+    rest.is_empty()
+}
+
+fn is_elementary(kind: &str) -> bool {
+    let bits = |size: &str, max: u32, step: u32| {
+        size.is_empty()
+            || size
+                .parse::<u32>()
+                .is_ok_and(|n| n > 0 && n <= max && n % step == 0)
+    };
+    match kind {
+        "address" | "bool" | "string" | "bytes" | "function" => true,
+        _ => {
+            if let Some(size) = kind.strip_prefix("uint") {
+                bits(size, 256, 8)
+            } else if let Some(size) = kind.strip_prefix("int") {
+                bits(size, 256, 8)
+            } else if let Some(size) = kind.strip_prefix("bytes") {
+                !size.is_empty() && bits(size, 32, 1)
+            } else {
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn loads_artifacts() {
+        let abi = r#"[{"type":"function","name":"swap","inputs":[{"name":"path","type":"address[]"}],"outputs":[]}]"#;
+        let hardhat = format!(
+            r#"{{"_format":"hh-sol-artifact-1","contractName":"Router","abi":{},"bytecode":"0x"}}"#,
+            abi
+        );
+        let foundry = format!(r#"{{"abi":{},"bytecode":{{"object":"0x"}}}}"#, abi);
+
+        let expected = from_json(abi).unwrap();
+        assert_eq!(expected.len(), 1);
+        assert_eq!(from_json(&hardhat).unwrap(), expected);
+        assert_eq!(from_json(&foundry).unwrap(), expected);
+        assert!(matches!(
+            from_json(r#"{"bytecode":"0x"}"#),
+            Err(AbiError::NotAnAbi)
+        ));
+        assert!(from_file("examples/quickswap.json").unwrap().len() > 1);
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let invalid = |entry: Value| match validate(&[json!({"type": "event", "name": "E"}), entry])
+        {
+            Err(AbiError::InvalidEntry { index: 1, reason }) => reason,
+            other => panic!("{:?}", other),
+        };
+
+        invalid(json!("function"));
+        invalid(json!({"type": "method", "name": "f"}));
+        invalid(json!({"type": "function", "inputs": []}));
+        invalid(json!({"name": "f", "inputs": {}}));
+        invalid(json!({"name": "f", "inputs": [{"name": "a"}]}));
+        invalid(json!({"name": "f", "inputs": [{"type": "uint7"}]}));
+        invalid(json!({"name": "f", "inputs": [{"type": "bytes33"}]}));
+        invalid(json!({"name": "f", "inputs": [{"type": "address[x]"}]}));
+        invalid(json!({"name": "f", "inputs": [{"type": "tuple"}]}));
+
+        let valid = json!({"name": "f", "inputs": [
+            {"type": "uint"},
+            {"type": "int24"},
+            {"type": "bytes32[2][]"},
+            {"type": "tuple[]", "components": [{"type": "address"}]}
+        ]});
+        assert!(validate(&[valid]).is_ok());
+    }
+
+    #[cfg(feature = "ethers")]
+    #[test]
+    fn parses_signatures() {
+        let abi = from_signatures(&[
+            "function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] path, address to, uint256 deadline)",
+            "event Swap(address indexed sender, uint amount0In)",
+        ])
+        .unwrap();
+        assert_eq!(abi.len(), 2);
+
+        let function = abi
+            .iter()
+            .find(|entry| entry["type"] == "function")
+            .unwrap();
+        assert_eq!(function["name"], "swapExactTokensForTokens");
+        assert_eq!(function["inputs"][2]["name"], "path");
+        assert_eq!(function["inputs"][2]["type"], "address[]");
+
+        assert!(matches!(
+            from_signatures(&["function swap(uint256"]),
+            Err(AbiError::InvalidSignature(_))
+        ));
+    }
+}
@@ -0,0 +1,366 @@
+//! Typed decoding of `contractCall` params, available with the `ethers` feature.
+//!
+//! The server decodes calldata with the ABI of the watched config and sends the
+//! arguments as JSON keyed by parameter name. The same ABI turns them back into
+//! ABI tokens, which then detokenize into tuples or the call structs generated
+//! by `abigen!`:
+//!
+//! ```ignore
+//! abigen!(Router, "router.json");
+//!
+//! let call: SwapExactTokensForTokensCall = contract_call.decode(&ROUTER_ABI)?;
+//! ```
+//!
+//! Struct arguments arrive as objects keyed by component name, which only the
+//! JSON ABI keeps, so decode those with `WatchConfig::decode_call`.
+use ethers::{
+    abi::{Abi, Detokenize, Function, ParamType, Token},
+    types::{Address, I256, U256},
+};
+use serde_json::Value;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::ws::models::{ContractCall, WatchConfig};
+
+/// Error thrown when a contract call does not match the ABI it is decoded with
+#[derive(Error, Debug)]
+pub enum DecodeError {
+    #[error("invalid abi: {0}")]
+    InvalidAbi(#[from] serde_json::Error),
+
+    #[error("method `{0}` is not in the abi")]
+    UnknownMethod(String),
+
+    #[error("`{method}` has no param `{param}`")]
+    MissingParam { method: String, param: String },
+
+    #[error("`{method}` param `{param}` is not a valid {expected}: {value}")]
+    InvalidParam {
+        method: String,
+        param: String,
+        expected: ParamType,
+        value: Value,
+    },
+
+    /// The params decoded but do not fit the requested Rust type
+    #[error("`{method}` params do not match the requested type: {reason}")]
+    Mismatch { method: String, reason: String },
+}
+
+impl WatchConfig {
+    /// Parses the ABI the server decodes contract calls of this config with
+    pub fn contract_abi(&self) -> Result<Abi, DecodeError> {
+        Ok(serde_json::from_value(Value::Array(self.abi.clone()))?)
+    }
+
+    /// Like `ContractCall::tokens`, but with this config's ABI, which also
+    /// names the components of struct arguments the server sends as objects
+    pub fn call_tokens(&self, call: &ContractCall) -> Result<Vec<Token>, DecodeError> {
+        let entries: Vec<&Value> = self
+            .abi
+            .iter()
+            .filter(|entry| {
+                entry["name"] == call.method_name.as_str()
+                    && matches!(
+                        entry.get("type").and_then(Value::as_str),
+                        None | Some("function")
+                    )
+            })
+            .collect();
+        call.resolve(&self.contract_abi()?, &entries)
+    }
+
+    /// Like `ContractCall::decode`, but with this config's ABI
+    pub fn decode_call<T: Detokenize>(&self, call: &ContractCall) -> Result<T, DecodeError> {
+        call.detokenize(self.call_tokens(call)?)
+    }
+}
+
+impl ContractCall {
+    /// Converts the params into the ABI tokens of the called method, in
+    /// declaration order. Overloaded methods resolve to the first overload
+    /// whose inputs all decode. A parsed `Abi` does not keep the names of
+    /// tuple components, so struct arguments sent as objects only decode
+    /// through `WatchConfig::call_tokens`.
+    pub fn tokens(&self, abi: &Abi) -> Result<Vec<Token>, DecodeError> {
+        self.resolve(abi, &[])
+    }
+
+    /// Decodes the params into `T`, e.g. a tuple of the method's argument
+    /// types or a call struct generated by `abigen!`
+    pub fn decode<T: Detokenize>(&self, abi: &Abi) -> Result<T, DecodeError> {
+        self.detokenize(self.tokens(abi)?)
+    }
+
+    /// Resolves the overloads of the method, `entries` are their JSON ABI
+    /// entries in the same order if known
+    fn resolve(&self, abi: &Abi, entries: &[&Value]) -> Result<Vec<Token>, DecodeError> {
+        let functions = abi
+            .functions_by_name(&self.method_name)
+            .map_err(|_| DecodeError::UnknownMethod(self.method_name.clone()))?;
+
+        let mut first_err = None;
+        for (i, function) in functions.iter().enumerate() {
+            match self.function_tokens(function, entries.get(i).copied()) {
+                Ok(tokens) => return Ok(tokens),
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_err.unwrap_or_else(|| DecodeError::UnknownMethod(self.method_name.clone())))
+    }
+
+    fn detokenize<T: Detokenize>(&self, tokens: Vec<Token>) -> Result<T, DecodeError> {
+        T::from_tokens(tokens).map_err(|e| DecodeError::Mismatch {
+            method: self.method_name.clone(),
+            reason: e.to_string(),
+        })
+    }
+
+    fn function_tokens(
+        &self,
+        function: &Function,
+        entry: Option<&Value>,
+    ) -> Result<Vec<Token>, DecodeError> {
+        function
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let value =
+                    self.params
+                        .get(&input.name)
+                        .ok_or_else(|| DecodeError::MissingParam {
+                            method: self.method_name.clone(),
+                            param: input.name.clone(),
+                        })?;
+                let param = entry.and_then(|entry| entry["inputs"].get(i));
+                to_token(&input.kind, param, value).ok_or_else(|| DecodeError::InvalidParam {
+                    method: self.method_name.clone(),
+                    param: input.name.clone(),
+                    expected: input.kind.clone(),
+                    value: value.clone(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Converts one JSON argument, amounts are usually sent as decimal strings.
+/// `param` is the argument's JSON ABI entry, which names tuple components.
+fn to_token(kind: &ParamType, param: Option<&Value>, value: &Value) -> Option<Token> {
+    let token = match kind {
+        ParamType::Address => Token::Address(Address::from_str(value.as_str()?).ok()?),
+        ParamType::Uint(_) => Token::Uint(to_u256(value)?),
+        ParamType::Int(_) => Token::Int(to_i256(value)?.into_raw()),
+        ParamType::Bool => Token::Bool(match value {
+            Value::Bool(b) => *b,
+            Value::String(s) => s.parse().ok()?,
+            _ => return None,
+        }),
+        ParamType::String => Token::String(value.as_str()?.to_string()),
+        ParamType::Bytes => Token::Bytes(to_bytes(value)?),
+        ParamType::FixedBytes(len) => {
+            let bytes = to_bytes(value)?;
+            if bytes.len() != *len {
+                return None;
+            }
+            Token::FixedBytes(bytes)
+        }
+        ParamType::Array(inner) => Token::Array(to_tokens(inner, param, value.as_array()?)?),
+        ParamType::FixedArray(inner, len) => {
+            let values = value.as_array()?;
+            if values.len() != *len {
+                return None;
+            }
+            Token::FixedArray(to_tokens(inner, param, values)?)
+        }
+        ParamType::Tuple(kinds) => {
+            let components = param.and_then(|param| param["components"].as_array());
+            let values: Vec<&Value> = match value {
+                Value::Array(values) => values.iter().collect(),
+                Value::Object(fields) => components?
+                    .iter()
+                    .map(|component| fields.get(component["name"].as_str()?))
+                    .collect::<Option<_>>()?,
+                _ => return None,
+            };
+            if values.len() != kinds.len() {
+                return None;
+            }
+            Token::Tuple(
+                kinds
+                    .iter()
+                    .zip(values)
+                    .enumerate()
+                    .map(|(i, (kind, value))| {
+                        let component = components.and_then(|components| components.get(i));
+                        to_token(kind, component, value)
+                    })
+                    .collect::<Option<_>>()?,
+            )
+        }
+    };
+    Some(token)
+}
+
+/// Array elements share the `param` of the array, which holds the components
+/// of `tuple[]`
+fn to_tokens(kind: &ParamType, param: Option<&Value>, values: &[Value]) -> Option<Vec<Token>> {
+    values
+        .iter()
+        .map(|value| to_token(kind, param, value))
+        .collect()
+}
+
+fn to_u256(value: &Value) -> Option<U256> {
+    match value {
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => U256::from_str(hex).ok(),
+            None => U256::from_dec_str(s).ok(),
+        },
+        Value::Number(n) => n.as_u64().map(U256::from),
+        _ => None,
+    }
+}
+
+fn to_i256(value: &Value) -> Option<I256> {
+    match value {
+        Value::String(s) => {
+            let (sign, abs) = match s.strip_prefix('-') {
+                Some(abs) => ("-", abs),
+                None => ("", s.as_str()),
+            };
+            match abs.strip_prefix("0x") {
+                Some(hex) => I256::from_hex_str(&format!("{}{}", sign, hex)).ok(),
+                None => I256::from_dec_str(s).ok(),
+            }
+        }
+        Value::Number(n) => n.as_i64().map(I256::from),
+        _ => None,
+    }
+}
+
+fn to_bytes(value: &Value) -> Option<Vec<u8>> {
+    let s = value.as_str()?;
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTER_ABI: &str = r#"[{"inputs":[{"internalType":"uint256","name":"amountIn","type":"uint256"},{"internalType":"uint256","name":"amountOutMin","type":"uint256"},{"internalType":"address[]","name":"path","type":"address[]"},{"internalType":"address","name":"to","type":"address"},{"internalType":"uint256","name":"deadline","type":"uint256"}],"name":"swapExactTokensForTokens","outputs":[{"internalType":"uint256[]","name":"amounts","type":"uint256[]"}],"stateMutability":"nonpayable","type":"function"}]"#;
+
+    fn call(params: &str) -> ContractCall {
+        serde_json::from_str(&format!(
+            r#"{{"contractType":"Uniswap V2: Router 2","contractAddress":"0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff","methodName":"swapExactTokensForTokens","params":{params},"contractName":"QuickSwap: Router"}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn decodes_swap() {
+        let abi: Abi = serde_json::from_str(ROUTER_ABI).unwrap();
+        let call = call(
+            r#"{"amountIn":"5000000000","amountOutMin":"180189367","path":["0xC250e9987A032ACAC293d838726C511E6E1C029d","0xc2132D05D31c914a87C6611C10748AEb04B58e8F"],"to":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","deadline":"3277746025"}"#,
+        );
+
+        let (amount_in, amount_out_min, path, to, deadline): (
+            U256,
+            U256,
+            Vec<Address>,
+            Address,
+            U256,
+        ) = call.decode(&abi).unwrap();
+        assert_eq!(amount_in, U256::from(5_000_000_000u64));
+        assert_eq!(amount_out_min, U256::from(180_189_367u64));
+        assert_eq!(path.len(), 2);
+        assert_eq!(
+            to,
+            Address::from_str("0x21F3bB63e775ccDf0CC04559Be142971D241aB0E").unwrap()
+        );
+        assert_eq!(deadline, U256::from(3_277_746_025u64));
+    }
+
+    #[test]
+    fn decodes_struct_params() {
+        let config = WatchConfig {
+            abi: serde_json::from_str(
+                r#"[{"name":"exactInputSingle","type":"function","inputs":[{"name":"params","type":"tuple","components":[{"name":"tokenIn","type":"address"},{"name":"amountIn","type":"uint256"},{"name":"tick","type":"int24"}]},{"name":"hops","type":"tuple[]","components":[{"name":"fee","type":"uint24"},{"name":"skew","type":"int256"}]}],"outputs":[]}]"#,
+            )
+            .unwrap(),
+            ..WatchConfig::new("0xE592427A0AEce92De3Edee1F18E0157C05861564")
+        };
+        let mut call = call(
+            r#"{"params":{"amountIn":"0x0de0b6b3a7640000","tick":"-0x10","tokenIn":"0xC250e9987A032ACAC293d838726C511E6E1C029d"},"hops":[{"fee":"3000","skew":"0x2a"},["500","-7"]]}"#,
+        );
+        call.method_name = "exactInputSingle".to_string();
+
+        let tokens = config.call_tokens(&call).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Tuple(vec![
+                    Token::Address(
+                        Address::from_str("0xC250e9987A032ACAC293d838726C511E6E1C029d").unwrap()
+                    ),
+                    Token::Uint(U256::exp10(18)),
+                    Token::Int(I256::from(-16).into_raw()),
+                ]),
+                Token::Array(vec![
+                    Token::Tuple(vec![
+                        Token::Uint(U256::from(3000)),
+                        Token::Int(I256::from(42).into_raw())
+                    ]),
+                    Token::Tuple(vec![
+                        Token::Uint(U256::from(500)),
+                        Token::Int(I256::from(-7).into_raw())
+                    ]),
+                ]),
+            ]
+        );
+
+        // Without the JSON ABI the component names are unknown
+        let abi = config.contract_abi().unwrap();
+        assert!(matches!(
+            call.tokens(&abi),
+            Err(DecodeError::InvalidParam { ref param, .. }) if param == "params"
+        ));
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let abi: Abi = serde_json::from_str(ROUTER_ABI).unwrap();
+
+        let missing = call(r#"{"amountIn":"1"}"#).tokens(&abi).unwrap_err();
+        assert!(
+            matches!(missing, DecodeError::MissingParam { ref param, .. } if param == "amountOutMin")
+        );
+
+        let invalid = call(
+            r#"{"amountIn":"abc","amountOutMin":"1","path":[],"to":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","deadline":"1"}"#,
+        )
+        .tokens(&abi)
+        .unwrap_err();
+        assert!(
+            matches!(invalid, DecodeError::InvalidParam { ref param, .. } if param == "amountIn")
+        );
+
+        let mut unknown = call("{}");
+        unknown.method_name = "transfer".to_string();
+        assert!(matches!(
+            unknown.tokens(&abi),
+            Err(DecodeError::UnknownMethod(_))
+        ));
+
+        let wrong_type = call(
+            r#"{"amountIn":"1","amountOutMin":"1","path":[],"to":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","deadline":"1"}"#,
+        )
+        .decode::<(Address, U256, Vec<Address>, Address, U256)>(&abi)
+        .unwrap_err();
+        assert!(matches!(wrong_type, DecodeError::Mismatch { .. }));
+    }
+}
@@ -0,0 +1,264 @@
+use futures_util::{future::poll_fn, stream::Stream, task::AtomicWaker};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+};
+
+use super::ws::{BufferConfig, Overflow};
+
+/// Bounded single consumer queue between the `WsServer` and one subscription,
+/// which applies the subscription's `Overflow` policy once full.
+pub(crate) fn channel<T>(mut config: BufferConfig) -> (Sender<T>, Receiver<T>) {
+    config.capacity = config.capacity.max(1);
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::new()),
+        config,
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        receiver_gone: AtomicBool::new(false),
+        receiver_task: AtomicWaker::new(),
+        sender_task: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    config: BufferConfig,
+    /// Items discarded because the queue was full
+    dropped: AtomicU64,
+    /// No more items will be pushed, the receiver ends once drained
+    closed: AtomicBool,
+    receiver_gone: AtomicBool,
+    receiver_task: AtomicWaker,
+    sender_task: AtomicWaker,
+}
+
+impl<T> Shared<T> {
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.receiver_task.wake();
+    }
+}
+
+/// Outcome of offering an item to a subscription
+#[derive(Debug, PartialEq)]
+pub(crate) enum Push<T> {
+    /// The item was queued, possibly evicting the oldest one
+    Queued,
+    /// The queue was full and the item was discarded
+    Dropped,
+    /// The queue is full and the policy is `Overflow::Block`, wait on `ready`
+    Full(T),
+    /// The receiver is gone or was disconnected for falling behind
+    Closed,
+}
+
+#[derive(Debug)]
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn try_push(&self, item: T) -> Push<T> {
+        let shared = &self.shared;
+        if shared.receiver_gone.load(Ordering::SeqCst) || shared.closed.load(Ordering::SeqCst) {
+            return Push::Closed;
+        }
+
+        let mut queue = shared.queue.lock().unwrap();
+        let push = if queue.len() < shared.config.capacity {
+            queue.push_back(item);
+            Push::Queued
+        } else {
+            match shared.config.overflow {
+                Overflow::Block => return Push::Full(item),
+                Overflow::DropOldest => {
+                    queue.pop_front();
+                    queue.push_back(item);
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    Push::Queued
+                }
+                Overflow::DropNewest => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    Push::Dropped
+                }
+                Overflow::Disconnect => {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    drop(queue);
+                    shared.close();
+                    return Push::Closed;
+                }
+            }
+        };
+        drop(queue);
+        shared.receiver_task.wake();
+        push
+    }
+
+    /// Queues a last item, regardless of capacity, and ends the stream after it
+    pub fn finish(&self, item: T) {
+        let shared = &self.shared;
+        if shared.receiver_gone.load(Ordering::SeqCst) || shared.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        shared.queue.lock().unwrap().push_back(item);
+        shared.close();
+    }
+
+    /// Whether the subscriber was disconnected by `Overflow::Disconnect`
+    pub fn is_lagging(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+            && !self.shared.receiver_gone.load(Ordering::SeqCst)
+    }
+
+    /// Waits until the queue has room or the receiver is gone
+    pub async fn ready(&self) {
+        poll_fn(|cx| self.poll_ready(cx)).await
+    }
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let shared = &self.shared;
+        let ready = || {
+            shared.receiver_gone.load(Ordering::SeqCst)
+                || shared.queue.lock().unwrap().len() < shared.config.capacity
+        };
+        if ready() {
+            return Poll::Ready(());
+        }
+        shared.sender_task.register(cx.waker());
+        if ready() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Number of items discarded so far because the consumer fell behind
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    fn try_pop(&self) -> Poll<Option<T>> {
+        let shared = &self.shared;
+        if let Some(item) = shared.queue.lock().unwrap().pop_front() {
+            shared.sender_task.wake();
+            return Poll::Ready(Some(item));
+        }
+        if shared.closed.load(Ordering::SeqCst) {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Poll::Ready(item) = self.try_pop() {
+            return Poll::Ready(item);
+        }
+        self.shared.receiver_task.register(cx.waker());
+        self.try_pop()
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_gone.store(true, Ordering::SeqCst);
+        self.shared.sender_task.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+
+    fn config(capacity: usize, overflow: Overflow) -> BufferConfig {
+        BufferConfig { capacity, overflow }
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_latest() {
+        let (sender, receiver) = channel(config(2, Overflow::DropOldest));
+        for i in 0..4 {
+            assert_eq!(sender.try_push(i), Push::Queued);
+        }
+        drop(sender);
+
+        assert_eq!(receiver.dropped(), 2);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_earliest() {
+        let (sender, receiver) = channel(config(2, Overflow::DropNewest));
+        assert_eq!(sender.try_push(0), Push::Queued);
+        assert_eq!(sender.try_push(1), Push::Queued);
+        assert_eq!(sender.try_push(2), Push::Dropped);
+        drop(sender);
+
+        assert_eq!(receiver.dropped(), 1);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn disconnect_ends_stream_after_backlog() {
+        let (sender, receiver) = channel(config(1, Overflow::Disconnect));
+        assert_eq!(sender.try_push(0), Push::Queued);
+        assert_eq!(sender.try_push(1), Push::Closed);
+        assert_eq!(sender.try_push(2), Push::Closed);
+
+        assert_eq!(receiver.dropped(), 1);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn finish_ignores_capacity() {
+        let (sender, receiver) = channel(config(1, Overflow::DropNewest));
+        assert_eq!(sender.try_push(0), Push::Queued);
+        sender.finish(1);
+        assert_eq!(sender.try_push(2), Push::Closed);
+
+        assert_eq!(receiver.dropped(), 0);
+        assert_eq!(receiver.collect::<Vec<_>>().await, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_reader() {
+        let (sender, mut receiver) = channel(config(1, Overflow::Block));
+        assert_eq!(sender.try_push(0), Push::Queued);
+        assert_eq!(sender.try_push(1), Push::Full(1));
+
+        let reader = tokio::spawn(async move { receiver.next().await });
+        sender.ready().await;
+        assert_eq!(reader.await.unwrap(), Some(0));
+        assert_eq!(sender.try_push(1), Push::Closed);
+    }
+}
@@ -0,0 +1,135 @@
+use std::{cmp::Ordering, fmt};
+
+use super::models::InvalidDelta;
+
+/// Signed decimal integer of any size, deltas of 18 decimals tokens can
+/// outgrow `i128` when summed
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Delta {
+    negative: bool,
+    /// Decimal digits, least significant first, without leading zeros
+    digits: Vec<u8>,
+}
+
+impl Delta {
+    pub fn parse(delta: &str) -> Result<Self, InvalidDelta> {
+        let (negative, abs) = match delta.strip_prefix('-') {
+            Some(abs) => (true, abs),
+            None => (false, delta.strip_prefix('+').unwrap_or(delta)),
+        };
+        if abs.is_empty() || !abs.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(InvalidDelta(delta.to_string()));
+        }
+        Ok(Self::new(
+            negative,
+            abs.bytes().rev().map(|b| b - b'0').collect(),
+        ))
+    }
+
+    fn new(negative: bool, mut digits: Vec<u8>) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        Self {
+            negative: negative && !digits.is_empty(),
+            digits,
+        }
+    }
+
+    pub fn add(&self, other: &Delta) -> Delta {
+        if self.negative == other.negative {
+            return Self::new(self.negative, add_digits(&self.digits, &other.digits));
+        }
+        match cmp_digits(&self.digits, &other.digits) {
+            Ordering::Less => Self::new(other.negative, sub_digits(&other.digits, &self.digits)),
+            _ => Self::new(self.negative, sub_digits(&self.digits, &other.digits)),
+        }
+    }
+}
+
+impl fmt::Display for Delta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.digits.is_empty() {
+            return write!(f, "0");
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        self.digits
+            .iter()
+            .rev()
+            .try_for_each(|digit| write!(f, "{}", digit))
+    }
+}
+
+fn cmp_digits(a: &[u8], b: &[u8]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut sum = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let digit = a.get(i).unwrap_or(&0) + b.get(i).unwrap_or(&0) + carry;
+        sum.push(digit % 10);
+        carry = digit / 10;
+    }
+    if carry > 0 {
+        sum.push(carry);
+    }
+    sum
+}
+
+/// `a - b` for `a >= b`
+fn sub_digits(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut difference = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, digit) in a.iter().enumerate() {
+        let subtrahend = b.get(i).unwrap_or(&0) + borrow;
+        borrow = u8::from(*digit < subtrahend);
+        difference.push(digit + 10 * borrow - subtrahend);
+    }
+    difference
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum(a: &str, b: &str) -> String {
+        Delta::parse(a)
+            .unwrap()
+            .add(&Delta::parse(b).unwrap())
+            .to_string()
+    }
+
+    #[test]
+    fn parses_canonical_deltas() {
+        let canonical = |delta: &str| Delta::parse(delta).map(|delta| delta.to_string());
+        assert_eq!(canonical("+005"), Ok("5".to_string()));
+        assert_eq!(canonical("-000"), Ok("0".to_string()));
+        assert_eq!(canonical("-0120"), Ok("-120".to_string()));
+        for invalid in ["", "-", "1e3", "0x10", " 1", "--1"] {
+            assert_eq!(canonical(invalid), Err(InvalidDelta(invalid.to_string())));
+        }
+    }
+
+    #[test]
+    fn adds_beyond_i128() {
+        assert_eq!(
+            sum("170141183460469231731687303715884105727", "1"),
+            "170141183460469231731687303715884105728"
+        );
+        assert_eq!(
+            sum("-170141183460469231731687303715884105728", "-1"),
+            "-170141183460469231731687303715884105729"
+        );
+        assert_eq!(sum("999", "1"), "1000");
+        assert_eq!(sum("1000", "-1001"), "-1");
+        assert_eq!(sum("-1000", "1"), "-999");
+        assert_eq!(sum("-42", "42"), "0");
+        assert_eq!(sum("0", "-7"), "-7");
+    }
+}
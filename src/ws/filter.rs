@@ -0,0 +1,501 @@
+//! Typed filters for `WatchConfig`.
+//!
+//! The server matches each event against the list of filters in a config, all
+//! of which must hold. A filter compares one field of the event, addressed by a
+//! dotted path such as `contractCall.params.path`, or groups other filters:
+//!
+//! ```
+//! use blocknative::ws::{filter::Filter, models::TransactionStatus};
+//!
+//! let filters = vec![
+//!     Filter::status(TransactionStatus::Pending),
+//!     Filter::eq("contractCall.methodName", "swapExactTokensForTokens"),
+//!     Filter::field("value").gte("1000000000000000000").into(),
+//!     Filter::any(vec![
+//!         Filter::eq("from", "0xa5e0829caced8ffdd4de3c43696c57f7d7a678ff"),
+//!         Filter::eq("to", "0xa5e0829caced8ffdd4de3c43696c57f7d7a678ff"),
+//!     ]),
+//! ];
+//! ```
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+use super::models::TransactionStatus;
+
+const TEXT: &str = "_text";
+const JOIN: &str = "_join";
+const TERMS: &str = "terms";
+const PARAMS: &str = "contractCall.params.";
+const METHOD: &str = "contractCall.methodName";
+const STATUS: &str = "status";
+
+/// How the terms of a group combine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Join {
+    And,
+    Or,
+}
+
+impl Join {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Join::And => "AND",
+            Join::Or => "OR",
+        }
+    }
+}
+
+/// Range comparison of a field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+impl Comparison {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Gt => "gt",
+            Comparison::Gte => "gte",
+            Comparison::Lt => "lt",
+            Comparison::Lte => "lte",
+        }
+    }
+
+    fn parse(op: &str) -> Option<Self> {
+        match op {
+            "gt" => Some(Comparison::Gt),
+            "gte" => Some(Comparison::Gte),
+            "lt" => Some(Comparison::Lt),
+            "lte" => Some(Comparison::Lte),
+            _ => None,
+        }
+    }
+}
+
+/// Test applied to the field of a `Filter::Field`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The field equals the value
+    Eq(Value),
+    /// Every comparison holds
+    Compare(Vec<(Comparison, Value)>),
+}
+
+/// One term of a config's filter list.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Tests the field at the dotted `path`
+    Field { path: String, condition: Condition },
+    /// Full text search over the event
+    Text(String),
+    /// Combines the terms
+    Group { join: Join, terms: Vec<Filter> },
+}
+
+impl Filter {
+    /// The field at `path` equals `value`
+    pub fn eq(path: impl Into<String>, value: impl Into<Value>) -> Self {
+        Filter::Field {
+            path: path.into(),
+            condition: Condition::Eq(value.into()),
+        }
+    }
+
+    /// Starts range comparisons on the field at `path`
+    pub fn field(path: impl Into<String>) -> FieldFilter {
+        FieldFilter {
+            path: path.into(),
+            comparisons: Vec::new(),
+        }
+    }
+
+    /// The transaction is in `status`
+    pub fn status(status: TransactionStatus) -> Self {
+        Filter::eq(STATUS, status.as_str())
+    }
+
+    /// Some field of the event contains `text`
+    pub fn text(text: impl Into<String>) -> Self {
+        Filter::Text(text.into())
+    }
+
+    /// At least one of `terms` holds
+    pub fn any(terms: Vec<Filter>) -> Self {
+        Filter::Group {
+            join: Join::Or,
+            terms,
+        }
+    }
+
+    /// All of `terms` hold
+    pub fn all(terms: Vec<Filter>) -> Self {
+        Filter::Group {
+            join: Join::And,
+            terms,
+        }
+    }
+
+    /// Filters on the field values of `map`, the form filters used to be
+    /// written in
+    pub fn from_map(map: HashMap<String, String>) -> Vec<Self> {
+        map.into_iter()
+            .map(|(path, value)| Filter::eq(path, value))
+            .collect()
+    }
+
+    /// Checks that the filter can match at all: paths, comparisons, groups
+    /// and statuses. Contract call paths are not checked, the server may know
+    /// the ABI of the contract already.
+    pub fn validate(&self) -> Result<(), FilterError> {
+        self.validate_against(None)
+    }
+
+    /// Checks the filter like `validate`, and its contract call paths against
+    /// the methods and params of `abi`, a list of JSON ABI entries
+    pub fn validate_abi(&self, abi: &[Value]) -> Result<(), FilterError> {
+        self.validate_against(Some(&AbiNames::new(abi)))
+    }
+
+    fn validate_against(&self, abi: Option<&AbiNames>) -> Result<(), FilterError> {
+        let (path, condition) = match self {
+            Filter::Text(_) => return Ok(()),
+            Filter::Group { terms, .. } if terms.is_empty() => return Err(FilterError::EmptyGroup),
+            Filter::Group { terms, .. } => {
+                return terms.iter().try_for_each(|term| term.validate_against(abi))
+            }
+            Filter::Field { path, condition } => (path, condition),
+        };
+
+        if path.is_empty() || path.split('.').any(str::is_empty) {
+            return Err(FilterError::InvalidPath(path.clone()));
+        }
+        if let Condition::Compare(comparisons) = condition {
+            if comparisons.is_empty() {
+                return Err(FilterError::EmptyComparison(path.clone()));
+            }
+        }
+
+        if let (STATUS, Condition::Eq(value)) = (path.as_str(), condition) {
+            if let None | Some(TransactionStatus::Unknown(_)) =
+                value.as_str().map(TransactionStatus::from)
+            {
+                return Err(FilterError::InvalidStatus(value.clone()));
+            }
+        }
+        match abi {
+            Some(abi) => abi.check(path, condition),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Range comparisons on one field, see `Filter::field`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldFilter {
+    path: String,
+    comparisons: Vec<(Comparison, Value)>,
+}
+
+impl FieldFilter {
+    pub fn gt(self, value: impl Into<Value>) -> Self {
+        self.compare(Comparison::Gt, value)
+    }
+
+    pub fn gte(self, value: impl Into<Value>) -> Self {
+        self.compare(Comparison::Gte, value)
+    }
+
+    pub fn lt(self, value: impl Into<Value>) -> Self {
+        self.compare(Comparison::Lt, value)
+    }
+
+    pub fn lte(self, value: impl Into<Value>) -> Self {
+        self.compare(Comparison::Lte, value)
+    }
+
+    fn compare(mut self, comparison: Comparison, value: impl Into<Value>) -> Self {
+        self.comparisons.push((comparison, value.into()));
+        self
+    }
+}
+
+impl From<FieldFilter> for Filter {
+    fn from(field: FieldFilter) -> Self {
+        Filter::Field {
+            path: field.path,
+            condition: Condition::Compare(field.comparisons),
+        }
+    }
+}
+
+/// Error thrown when a filter can never match or the server would refuse it
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum FilterError {
+    #[error("invalid filter path `{0}`")]
+    InvalidPath(String),
+
+    #[error("no comparison given for `{0}`")]
+    EmptyComparison(String),
+
+    #[error("filter group without terms")]
+    EmptyGroup,
+
+    #[error("invalid transaction status {0}")]
+    InvalidStatus(Value),
+
+    #[error("contract call filters require an abi")]
+    MissingAbi,
+
+    #[error("method `{0}` is not in the abi")]
+    UnknownMethod(String),
+
+    #[error("`{0}` is not a param of any method in the abi")]
+    UnknownParam(String),
+}
+
+/// Method and param names of a JSON ABI
+struct AbiNames {
+    methods: HashSet<String>,
+    params: HashSet<String>,
+}
+
+impl AbiNames {
+    fn new(abi: &[Value]) -> Self {
+        let functions = abi
+            .iter()
+            .filter(|entry| entry["type"] == "function" || entry.get("type").is_none());
+        let mut methods = HashSet::new();
+        let mut params = HashSet::new();
+        for function in functions {
+            if let Some(name) = function["name"].as_str() {
+                methods.insert(name.to_string());
+            }
+            let inputs = function["inputs"].as_array().into_iter().flatten();
+            params.extend(inputs.filter_map(|input| input["name"].as_str().map(String::from)));
+        }
+        Self { methods, params }
+    }
+
+    fn require(&self) -> Result<(), FilterError> {
+        if self.methods.is_empty() {
+            return Err(FilterError::MissingAbi);
+        }
+        Ok(())
+    }
+
+    /// Checks a contract call path and the method it compares with
+    fn check(&self, path: &str, condition: &Condition) -> Result<(), FilterError> {
+        if let (METHOD, Condition::Eq(value)) = (path, condition) {
+            let method = value.as_str().unwrap_or_default();
+            self.require()?;
+            if !self.methods.contains(method) {
+                return Err(FilterError::UnknownMethod(method.to_string()));
+            }
+        }
+        if let Some(param) = path.strip_prefix(PARAMS) {
+            self.require()?;
+            let name = param.split('.').next().unwrap_or_default();
+            if !self.params.contains(name) {
+                return Err(FilterError::UnknownParam(path.to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Value::from(self).serialize(serializer)
+    }
+}
+
+impl From<&Filter> for Value {
+    fn from(filter: &Filter) -> Self {
+        let mut map = Map::new();
+        match filter {
+            Filter::Field { path, condition } => {
+                let value = match condition {
+                    Condition::Eq(value) => value.clone(),
+                    Condition::Compare(comparisons) => Value::Object(
+                        comparisons
+                            .iter()
+                            .map(|(op, value)| (op.as_str().to_string(), value.clone()))
+                            .collect(),
+                    ),
+                };
+                map.insert(path.clone(), value);
+            }
+            Filter::Text(text) => {
+                map.insert(TEXT.to_string(), Value::from(text.as_str()));
+            }
+            Filter::Group { join, terms } => {
+                map.insert(JOIN.to_string(), Value::from(join.as_str()));
+                map.insert(
+                    TERMS.to_string(),
+                    Value::Array(terms.iter().map(Value::from).collect()),
+                );
+            }
+        }
+        Value::Object(map)
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Filter::try_from(value).map_err(de::Error::custom)
+    }
+}
+
+impl TryFrom<Value> for Filter {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let mut map = match value {
+            Value::Object(map) => map,
+            other => return Err(format!("expected a filter object, got {}", other)),
+        };
+
+        if let Some(join) = map.remove(JOIN) {
+            let join = match join.as_str() {
+                Some("OR") => Join::Or,
+                Some("AND") => Join::And,
+                _ => return Err(format!("invalid {} {}", JOIN, join)),
+            };
+            let terms = match map.remove(TERMS) {
+                Some(Value::Array(terms)) => terms
+                    .into_iter()
+                    .map(Filter::try_from)
+                    .collect::<Result<_, _>>()?,
+                _ => return Err(format!("{} without {}", JOIN, TERMS)),
+            };
+            return Ok(Filter::Group { join, terms });
+        }
+
+        if map.len() != 1 {
+            return Err(format!("expected a single field, got {}", map.len()));
+        }
+        let (path, value) = map.into_iter().next().unwrap();
+        if path == TEXT {
+            return match value {
+                Value::String(text) => Ok(Filter::Text(text)),
+                other => Err(format!("invalid {} {}", TEXT, other)),
+            };
+        }
+
+        let condition = match value {
+            Value::Object(ops) if !ops.is_empty() => Condition::Compare(
+                ops.into_iter()
+                    .map(|(op, value)| match Comparison::parse(&op) {
+                        Some(op) => Ok((op, value)),
+                        None => Err(format!("unknown comparison `{}`", op)),
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            value => Condition::Eq(value),
+        };
+        Ok(Filter::Field { path, condition })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn serializes_grammar() {
+        let filters = vec![
+            Filter::status(TransactionStatus::Pending),
+            Filter::eq(
+                "contractCall.params.path",
+                "0xC250e9987A032ACAC293d838726C511E6E1C029d",
+            ),
+            Filter::field("value").gt(1000).lte("5000").into(),
+            Filter::text("0xc250e9987a032acac293d838726c511e6e1c029d"),
+            Filter::any(vec![
+                Filter::eq("from", "0x1"),
+                Filter::all(vec![Filter::eq("to", "0x2"), Filter::eq("asset", "ETH")]),
+            ]),
+        ];
+
+        let value = serde_json::to_value(&filters).unwrap();
+        assert_eq!(
+            value,
+            json!([
+                {"status": "pending"},
+                {"contractCall.params.path": "0xC250e9987A032ACAC293d838726C511E6E1C029d"},
+                {"value": {"gt": 1000, "lte": "5000"}},
+                {"_text": "0xc250e9987a032acac293d838726c511e6e1c029d"},
+                {"_join": "OR", "terms": [
+                    {"from": "0x1"},
+                    {"_join": "AND", "terms": [{"to": "0x2"}, {"asset": "ETH"}]}
+                ]}
+            ])
+        );
+
+        let parsed: Vec<Filter> = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, filters);
+    }
+
+    #[test]
+    fn validates_against_abi() {
+        // Without an explicit ABI the server's copy of it decides
+        assert!(Filter::eq("contractCall.params.amountOut", "1")
+            .validate()
+            .is_ok());
+        assert_eq!(
+            Filter::eq("status", "mined").validate(),
+            Err(FilterError::InvalidStatus(json!("mined")))
+        );
+
+        let abi = vec![json!({
+            "type": "function",
+            "name": "swapExactTokensForTokens",
+            "inputs": [
+                {"name": "amountIn", "type": "uint256"},
+                {"name": "path", "type": "address[]"}
+            ]
+        })];
+
+        assert!(Filter::eq("contractCall.params.path", "0x1")
+            .validate_abi(&abi)
+            .is_ok());
+        assert!(
+            Filter::eq("contractCall.methodName", "swapExactTokensForTokens")
+                .validate_abi(&abi)
+                .is_ok()
+        );
+        assert_eq!(
+            Filter::eq("contractCall.params.amountOut", "1").validate_abi(&abi),
+            Err(FilterError::UnknownParam(
+                "contractCall.params.amountOut".to_string()
+            ))
+        );
+        assert_eq!(
+            Filter::any(vec![Filter::eq("contractCall.methodName", "swap")]).validate_abi(&abi),
+            Err(FilterError::UnknownMethod("swap".to_string()))
+        );
+        assert_eq!(
+            Filter::eq("contractCall.params.path", "0x1").validate_abi(&[]),
+            Err(FilterError::MissingAbi)
+        );
+        assert_eq!(
+            Filter::eq("status", "mined").validate_abi(&abi),
+            Err(FilterError::InvalidStatus(json!("mined")))
+        );
+        assert_eq!(
+            Filter::from(Filter::field("value")).validate_abi(&abi),
+            Err(FilterError::EmptyComparison("value".to_string()))
+        );
+        assert_eq!(
+            Filter::all(vec![]).validate_abi(&abi),
+            Err(FilterError::EmptyGroup)
+        );
+    }
+}
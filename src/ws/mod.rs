@@ -1,2 +1,9 @@
+pub mod abi;
+mod buffer;
+mod delta;
+pub mod filter;
 pub mod models;
+mod router;
+#[cfg(feature = "ethers")]
+pub mod typed;
 pub mod ws;
@@ -2,12 +2,170 @@ use chrono::Utc;
 // Code adapted from: https://github.com/althea-net/guac_rs/tree/master/web3/src/jsonrpc
 // use ethers_core::types::U256;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::{collections::HashMap, fmt};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, fmt};
 use thiserror::Error;
 
+use super::{
+    delta::Delta,
+    filter::{Filter, FilterError},
+};
 use crate::models::Blockchain;
 
+/// Declares an enum over the string values of a protocol field. Values the
+/// server adds later decode into `Unknown` instead of failing.
+macro_rules! string_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident => $value:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(from = "String", into = "String")]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)*
+            /// A value this version of the crate does not know about
+            Unknown(String),
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $value,)*
+                    Self::Unknown(value) => value,
+                }
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                match value {
+                    $($value => Self::$variant,)*
+                    _ => Self::Unknown(value.to_string()),
+                }
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                match Self::from(value.as_str()) {
+                    Self::Unknown(_) => Self::Unknown(value),
+                    known => known,
+                }
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                match value {
+                    $name::Unknown(value) => value,
+                    known => known.as_str().to_string(),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+    };
+}
+
+string_enum! {
+    /// `categoryCode` of requests and events
+    pub enum CategoryCode {
+        Initialize => "initialize",
+        ActiveTransaction => "activeTransaction",
+        ActiveAddress => "activeAddress",
+        AccountAddress => "accountAddress",
+        Configs => "configs",
+    }
+}
+
+string_enum! {
+    /// `eventCode` of requests and events
+    pub enum EventCode {
+        CheckDappId => "checkDappId",
+        TxSent => "txSent",
+        Watch => "watch",
+        Unwatch => "unwatch",
+        Put => "put",
+        Delete => "delete",
+        TxPool => "txPool",
+        TxPoolSimulation => "txPoolSimulation",
+        TxConfirmed => "txConfirmed",
+        TxSpeedUp => "txSpeedUp",
+        TxCancel => "txCancel",
+        TxFailed => "txFailed",
+        TxDropped => "txDropped",
+        TxStuck => "txStuck",
+        TxRequest => "txRequest",
+        TxReplaceAlert => "txReplaceAlert",
+    }
+}
+
+impl EventCode {
+    /// Whether the client sends this code, which the server echoes in its reply
+    pub fn is_request(&self) -> bool {
+        matches!(
+            self,
+            EventCode::CheckDappId
+                | EventCode::TxSent
+                | EventCode::Watch
+                | EventCode::Unwatch
+                | EventCode::Put
+                | EventCode::Delete
+        )
+    }
+
+    /// Whether the transaction hash sees no further events after this one
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            EventCode::TxConfirmed
+                | EventCode::TxFailed
+                | EventCode::TxDropped
+                | EventCode::TxSpeedUp
+                | EventCode::TxCancel
+        )
+    }
+}
+
+string_enum! {
+    /// `status` of a server message
+    #[derive(Default)]
+    pub enum Status {
+        #[default]
+        Ok => "ok",
+        Error => "error",
+    }
+}
+
+string_enum! {
+    /// `status` of a transaction
+    pub enum TransactionStatus {
+        Pending => "pending",
+        PendingSimulation => "pending-simulation",
+        Confirmed => "confirmed",
+        Speedup => "speedup",
+        Cancel => "cancel",
+        Failed => "failed",
+        Dropped => "dropped",
+        Stuck => "stuck",
+    }
+}
+
+string_enum! {
+    /// Direction of a transaction relative to the watched address
+    pub enum Direction {
+        Incoming => "incoming",
+        Outgoing => "outgoing",
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Error)]
 /// A JSON-RPC 2.0 error
 pub struct JsonRpcError {
@@ -37,12 +195,12 @@ pub struct Message {
     blockchain: Blockchain,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TxDescriptor {
     pub hash: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AccountDescriptor {
     pub address: String,
 }
@@ -82,8 +240,8 @@ pub struct Request<'a, T> {
     dapp_id: &'a str,
     blockchain: Blockchain,
     version: &'a str,
-    category_code: String,
-    event_code: String,
+    category_code: CategoryCode,
+    event_code: EventCode,
     #[serde(flatten)]
     params: T,
 }
@@ -99,8 +257,8 @@ impl<'a, T> Request<'a, T> {
     pub fn new(
         dapp_id: &'a str,
         blockchain: Blockchain,
-        method: &'a str,
-        event_code: &'a str,
+        category_code: CategoryCode,
+        event_code: EventCode,
         params: T,
     ) -> Self {
         Self {
@@ -108,8 +266,8 @@ impl<'a, T> Request<'a, T> {
             dapp_id,
             blockchain,
             version: "2",
-            category_code: method.to_string(),
-            event_code: event_code.to_string(),
+            category_code,
+            event_code,
             params,
         }
     }
@@ -138,6 +296,9 @@ pub enum GasInfo {
 pub enum InternalTransaction {
     DelegateCall(InternalTransactionDetails),
     Call(InternalTransactionDetails),
+    StaticCall(InternalTransactionDetails),
+    Create(InternalTransactionDetails),
+    Create2(InternalTransactionDetails),
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -151,10 +312,131 @@ pub struct InternalTransactionDetails {
     pub value: String,
 }
 
+/// How a transaction changes the balances of one address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetBalanceChange {
+    pub address: String,
+    pub balance_changes: Vec<BalanceChange>,
+}
+
+/// Change of the balance of one asset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceChange {
+    /// Signed amount in the asset's smallest unit, e.g. `-1000000`
+    pub delta: String,
+    pub asset: Asset,
+    /// Transfers which make up the delta
+    #[serde(default)]
+    pub breakdown: Vec<BalanceTransfer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Asset {
+    /// `ether`, `erc20`, ...
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub symbol: String,
+    /// Token contract, `None` for the native currency
+    pub contract_address: Option<String>,
+    pub decimals: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceTransfer {
+    pub counterparty: String,
+    /// Signed amount in the asset's smallest unit
+    pub amount: String,
+}
+
+/// Error thrown when a balance delta is not a decimal integer
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("invalid balance delta `{0}`")]
+pub struct InvalidDelta(pub String);
+
+impl NetBalanceChange {
+    /// Merges `changes` by address, summing the deltas of the same asset
+    /// exactly, however large. Addresses are lowercased and deltas are
+    /// written without sign prefix `+` or leading zeros.
+    pub fn aggregate(
+        changes: &[NetBalanceChange],
+    ) -> Result<BTreeMap<String, Vec<BalanceChange>>, InvalidDelta> {
+        let mut by_address: BTreeMap<String, Vec<BalanceChange>> = BTreeMap::new();
+        for change in changes {
+            let merged = by_address.entry(change.address.to_lowercase()).or_default();
+            for balance_change in &change.balance_changes {
+                let delta = Delta::parse(&balance_change.delta)?;
+                let existing = merged
+                    .iter_mut()
+                    .find(|existing| existing.asset.is_same(&balance_change.asset));
+                match existing {
+                    Some(existing) => {
+                        existing.delta = Delta::parse(&existing.delta)?.add(&delta).to_string();
+                        existing
+                            .breakdown
+                            .extend(balance_change.breakdown.iter().cloned());
+                    }
+                    None => merged.push(BalanceChange {
+                        delta: delta.to_string(),
+                        ..balance_change.clone()
+                    }),
+                }
+            }
+        }
+        Ok(by_address)
+    }
+}
+
+impl BalanceChange {
+    /// The delta as a number, `None` if it does not fit an `i128`
+    pub fn amount(&self) -> Option<i128> {
+        self.delta.parse().ok()
+    }
+}
+
+impl Asset {
+    /// Whether both are the same token, or both the native currency
+    fn is_same(&self, other: &Asset) -> bool {
+        match (&self.contract_address, &other.contract_address) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            (None, None) => self.kind == other.kind && self.symbol == other.symbol,
+            _ => false,
+        }
+    }
+}
+
+/// How the platform simulated a pending transaction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimDetails {
+    /// Block the transaction was simulated on top of
+    pub block_number: u64,
+    pub performance_profile: Option<PerformanceProfile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PerformanceProfile {
+    #[serde(default)]
+    pub breakdown: Vec<ProfileStep>,
+    /// Milliseconds from detecting the transaction to dispatching the event
+    pub e2e_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileStep {
+    pub label: String,
+    pub time_stamp: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
-    pub status: String,
+    pub status: TransactionStatus,
     pub monitor_id: String,
     pub monitor_version: String,
     #[serde(flatten)]
@@ -178,33 +460,30 @@ pub struct Transaction {
     #[serde(flatten)]
     pub watch_info: Option<WatchedAddressInfo>,
     pub internal_transactions: Option<Vec<InternalTransaction>>,
+    /// Balance deltas of the simulated transaction, on `txPoolSimulation`
+    pub net_balance_changes: Option<Vec<NetBalanceChange>>,
+    pub sim_details: Option<SimDetails>,
+    /// Hash this transaction replaces, on `txSpeedUp` and `txCancel`
+    pub original_hash: Option<String>,
+    /// Hash of the transaction which replaced this one
+    pub replace_hash: Option<String>,
 }
 
-#[cfg(feature = "ethers")]
-impl From<Transaction> for ethers::prelude::Transaction {
-    fn from(val: Transaction) -> Self {
-        ethers::prelude::Transaction {
-            from: val.from.parse().unwrap(),
-            to: Some(val.to.parse().unwrap()),
-            gas: val.gas.into(),
-            gas_price: Some(val.gas_price.parse().unwrap()),
-            value: val.value.parse().unwrap(),
-            nonce: val.nonce.into(),
-            block_hash: None,
-            block_number: None,
-            transaction_index: None,
-            input: hex::decode(val.input.strip_prefix("0x").unwrap())
-                .unwrap()
-                .into(),
-            v: val.v.parse().unwrap(),
-            r: val.r.parse().unwrap(),
-            s: val.s.parse().unwrap(),
-            transaction_type: val.type_field.map(|n| n.into()),
-            access_list: None,
-            max_priority_fee_per_gas: None,
-            max_fee_per_gas: None,
-            chain_id: None,
-            hash: val.hash.parse().unwrap(),
+impl Transaction {
+    /// `net_balance_changes` merged by address, see `NetBalanceChange::aggregate`
+    pub fn balance_changes_by_address(
+        &self,
+    ) -> Result<BTreeMap<String, Vec<BalanceChange>>, InvalidDelta> {
+        NetBalanceChange::aggregate(self.net_balance_changes.as_deref().unwrap_or_default())
+    }
+
+    /// The hash this transaction replaced and the hash which replaces it, if
+    /// the event links a speed up or cancellation to its original
+    pub fn replacement(&self) -> Option<(&str, &str)> {
+        match (&self.original_hash, &self.replace_hash) {
+            (Some(original), _) => Some((original, &self.hash)),
+            (None, Some(replacement)) => Some((&self.hash, replacement)),
+            (None, None) => None,
         }
     }
 }
@@ -232,7 +511,7 @@ pub struct ConfirmedInfo {
 #[serde(rename_all = "camelCase")]
 pub struct WatchedAddressInfo {
     pub watched_address: String,
-    pub direction: String,
+    pub direction: Direction,
     pub counterparty: String,
 }
 
@@ -250,12 +529,70 @@ pub struct ContractCall {
 #[serde(rename_all = "camelCase")]
 pub struct Event {
     pub time_stamp: String,
-    pub category_code: String,
-    pub event_code: String,
+    pub category_code: CategoryCode,
+    pub event_code: EventCode,
     pub dapp_id: String,
     pub blockchain: Blockchain,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub contract_call: Option<ContractCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub transaction: Option<Transaction>,
+    /// Fields the models do not know, and with `Decode::Lenient` the
+    /// `contractCall` or `transaction` which failed to decode
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Event {
+    /// Decodes `value`, moving a `contractCall` or `transaction` which does not
+    /// fit its model into `extra`
+    fn from_value_lenient(mut value: Value) -> Option<Self> {
+        if let Value::Object(object) = &mut value {
+            let mut invalid = Map::new();
+            if let Some(call) = object.get("contractCall") {
+                if ContractCall::deserialize(call).is_err() {
+                    invalid.extend(object.remove_entry("contractCall"));
+                }
+            }
+            if let Some(tx) = object.get("transaction") {
+                if Transaction::deserialize(tx).is_err() {
+                    invalid.extend(object.remove_entry("transaction"));
+                }
+            }
+            let mut event: Self = serde_json::from_value(value).ok()?;
+            event.extra.extend(invalid);
+            return Some(event);
+        }
+        None
+    }
+}
+
+/// A transaction seen on a watched account
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountEvent {
+    pub address: String,
+    pub direction: Direction,
+    pub counterparty: String,
+    pub event_code: EventCode,
+    pub contract_call: Option<ContractCall>,
+    pub transaction: Transaction,
+}
+
+impl AccountEvent {
+    /// Extracts the account event from a response, if it carries one
+    pub fn from_response(resp: Response) -> Option<Self> {
+        let event = resp.event?;
+        let transaction = event.transaction?;
+        let info = transaction.watch_info.clone()?;
+        Some(Self {
+            address: info.watched_address,
+            direction: info.direction,
+            counterparty: info.counterparty,
+            event_code: event.event_code,
+            contract_call: event.contract_call,
+            transaction,
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -265,11 +602,44 @@ pub struct Response {
     pub server_version: String,
     pub time_stamp: String,
     pub connection_id: String,
-    pub status: String,
+    pub status: Status,
     pub raw: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub event: Option<Event>,
     pub reason: Option<String>,
     pub dispatch_timestamp: Option<String>,
+    /// Fields the models do not know, and with `Decode::Lenient` the `event`
+    /// which failed to decode
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Response {
+    /// Decodes as much of `value` as fits the models. Parts of the event which
+    /// do not, or the whole event if its required fields are broken, are kept
+    /// as JSON in `extra`. Fails only if the envelope itself is invalid.
+    pub fn from_value_lenient(value: Value) -> Result<Self, serde_json::Error> {
+        let strict = match Self::deserialize(&value) {
+            Ok(resp) => return Ok(resp),
+            Err(e) => e,
+        };
+        let mut object = match value {
+            Value::Object(object) => object,
+            _ => return Err(strict),
+        };
+
+        let event = object.remove("event");
+        let mut resp = Self::deserialize(Value::Object(object)).map_err(|_| strict)?;
+        if let Some(event) = event.filter(|event| !event.is_null()) {
+            match Event::from_value_lenient(event.clone()) {
+                Some(event) => resp.event = Some(event),
+                None => {
+                    resp.extra.insert("event".to_string(), event);
+                }
+            }
+        }
+        Ok(resp)
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -277,7 +647,7 @@ pub struct Response {
 pub struct HelloMsg {
     pub version: i64,
     pub server_version: String,
-    pub status: String,
+    pub status: Status,
     #[serde(rename = "showUX")]
     pub show_ux: bool,
     pub connection_id: String,
@@ -287,20 +657,159 @@ pub struct HelloMsg {
 #[serde(rename_all = "camelCase")]
 pub struct WatchConfig {
     pub scope: String,
-    pub filters: Vec<HashMap<String, String>>,
+    pub filters: Vec<Filter>,
     pub abi: Vec<Value>,
     pub watch_address: bool,
 }
 
+impl WatchConfig {
+    /// Scope of the config which filters the whole mempool
+    pub const GLOBAL_SCOPE: &'static str = "global";
+
+    /// Watches the contract or account at `scope`, without filters or ABI
+    pub fn new(scope: impl Into<String>) -> Self {
+        Self {
+            scope: scope.into(),
+            filters: Vec::new(),
+            abi: Vec::new(),
+            watch_address: true,
+        }
+    }
+
+    /// Watches every transaction in the mempool which matches the config's
+    /// filters, e.g. `Filter::field("value").gt(..)`. The server keeps a single
+    /// global config per connection: subscribing another one replaces its
+    /// filters, every global stream receives the events of the latest, and the
+    /// config is deleted once the last of them is dropped.
+    pub fn global() -> Self {
+        Self {
+            watch_address: false,
+            ..Self::new(Self::GLOBAL_SCOPE)
+        }
+    }
+
+    /// Whether this is the global config rather than an address scoped one
+    pub fn is_global(&self) -> bool {
+        self.scope == Self::GLOBAL_SCOPE
+    }
+
+    /// Replaces the ABI contract calls are decoded with, see `ws::abi` for
+    /// loaders
+    pub fn with_abi(mut self, abi: Vec<Value>) -> Self {
+        self.abi = abi;
+        self
+    }
+
+    /// Adds a filter, events must match all filters of the config
+    pub fn filter(mut self, filter: impl Into<Filter>) -> Self {
+        self.filters.push(filter.into());
+        self
+    }
+
+    /// Checks that every filter can match, see `Filter::validate`
+    pub fn validate(&self) -> Result<(), FilterError> {
+        self.filters.iter().try_for_each(Filter::validate)
+    }
+
+    /// Checks every filter against the config's ABI as well, for configs whose
+    /// ABI covers the contract calls they filter on
+    pub fn validate_abi(&self) -> Result<(), FilterError> {
+        self.filters
+            .iter()
+            .try_for_each(|filter| filter.validate_abi(&self.abi))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct WatchRequest {
     pub config: WatchConfig,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConfigDescriptor {
+    pub scope: String,
+}
+
+/// Server reply to one of our requests, which is echoed back in `event`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reply {
+    pub status: Status,
+    pub reason: Option<String>,
+    pub event: Option<ReplyEvent>,
+}
+
+impl Reply {
+    /// Whether the message `value` answers a request rather than reporting an
+    /// event: errors, and echoes of the codes the client sends
+    pub fn is_reply(value: &Value) -> bool {
+        value["status"] == Status::Error.as_str()
+            || value["event"]["eventCode"]
+                .as_str()
+                .is_some_and(|code| EventCode::from(code).is_request())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplyEvent {
+    pub category_code: CategoryCode,
+    pub event_code: EventCode,
+    pub config: Option<ConfigDescriptor>,
+    pub transaction: Option<TxDescriptor>,
+    pub account: Option<AccountDescriptor>,
+}
+
+impl ReplyEvent {
+    /// Scope, hash or address the echoed request was about
+    pub fn key(&self) -> Option<&str> {
+        self.config
+            .as_ref()
+            .map(|config| config.scope.as_str())
+            .or_else(|| self.transaction.as_ref().map(|tx| tx.hash.as_str()))
+            .or_else(|| {
+                self.account
+                    .as_ref()
+                    .map(|account| account.address.as_str())
+            })
+    }
+}
+
+/// Removes the config registered for `scope`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnwatchRequest {
+    pub config: ConfigDescriptor,
+}
+
+impl UnwatchRequest {
+    pub fn new(scope: String) -> Self {
+        Self {
+            config: ConfigDescriptor { scope },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_string_enums() {
+        let code: EventCode = serde_json::from_str(r#""txSpeedUp""#).unwrap();
+        assert_eq!(code, EventCode::TxSpeedUp);
+        assert_eq!(serde_json::to_string(&code).unwrap(), r#""txSpeedUp""#);
+
+        let code: EventCode = serde_json::from_str(r#""txSomethingNew""#).unwrap();
+        assert_eq!(code, EventCode::Unknown("txSomethingNew".to_string()));
+        assert_eq!(serde_json::to_string(&code).unwrap(), r#""txSomethingNew""#);
+
+        assert_eq!(
+            TransactionStatus::from("pending-simulation"),
+            TransactionStatus::PendingSimulation
+        );
+        assert_eq!(CategoryCode::ActiveAddress.to_string(), "activeAddress");
+    }
+
     #[test]
     fn test_decode() {
         let json = r#"{"version":0,"serverVersion":"0.123.2","timeStamp":"2021-12-07T10:20:25.212Z","connectionId":"C4-bc4de41f-c42f-460a-af83-28ad95286ab0","status":"ok","event":{"timeStamp":"2021-12-07T10:20:25.212Z","categoryCode":"activeAddress","eventCode":"txConfirmed","dappId":"7d507b2c-48f2-48bb-bd79-fc16ced6f8cf","blockchain":{"system":"ethereum","network":"matic-main"},"contractCall":{"contractType":"Uniswap V2: Router 2","contractAddress":"0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff","methodName":"swapExactTokensForTokens","params":{"amountIn":"5000000000","amountOutMin":"180189367","path":["0xC250e9987A032ACAC293d838726C511E6E1C029d","0xa3Fa99A148fA48D14Ed51d610c367C61876997F1","0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174","0xc2132D05D31c914a87C6611C10748AEb04B58e8F"],"to":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","deadline":"3277746025"},"contractName":"QuickSwap: Router"},"transaction":{"status":"confirmed","monitorId":"Geth_137_C_PROD","monitorVersion":"0.102.0","timePending":"3146","blocksPending":3,"pendingTimeStamp":"2021-12-07T10:20:22.066Z","pendingBlockNumber":22235980,"hash":"0xe0b1cf2bea578f49ba78cacd0d12d9c013f07cdd987936e71965edf6bd972b78","from":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","to":"0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff","value":"0","gas":387473,"nonce":45,"blockHash":"0xa814777d863e89c2b565ad4947e37e48bc5d8407b4065303c6371de519980d89","blockNumber":22235983,"v":"0x136","r":"0xb1fa90713d69a05869823607cc4bc67de6c7d4599b9fe8b00c54d8bc902739f9","s":"0x297a6aba5a47be29475d037b41619ad4003048e82305f20a3b18927cbfe2a343","input":"0x38ed1739000000000000000000000000000000000000000000000000000000012a05f200000000000000000000000000000000000000000000000000000000000abd78b700000000000000000000000000000000000000000000000000000000000000a000000000000000000000000021f3bb63e775ccdf0cc04559be142971d241ab0e00000000000000000000000000000000000000000000000000000000c35e6f690000000000000000000000000000000000000000000000000000000000000004000000000000000000000000c250e9987a032acac293d838726c511e6e1c029d000000000000000000000000a3fa99a148fa48d14ed51d610c367c61876997f10000000000000000000000002791bca1f2de4661ed88a30c99a7a9449aa84174000000000000000000000000c2132d05d31c914a87c6611c10748aeb04b58e8f","gasPrice":"113000000000","gasPriceGwei":113,"gasUsed":"236672","transactionIndex":1,"asset":"","blockTimeStamp":"2021-12-07T10:20:25.000Z","watchedAddress":"0xa5e0829caced8ffdd4de3c43696c57f7d7a678ff","direction":"incoming","counterparty":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E"}},"dispatchTimestamp":"2021-12-07T10:20:25.247Z"}"#;
@@ -354,4 +863,113 @@ mod tests {
         let json = r#"{"version":0,"serverVersion":"0.127.0","timeStamp":"2022-02-05T05:32:53.837Z","connectionId":"d4-bf0707bb-a594-478a-be8d-9cbe0bf9dc37","status":"ok","event":{"timeStamp":"2022-02-05T05:32:53.837Z","categoryCode":"activeAddress","eventCode":"txPool","dappId":"7d507b2c-48f2-48bb-bd79-fc16ced6f8cf","blockchain":{"system":"ethereum","network":"main"},"contractCall":{"methodName":"purchase","params":{"maturity":"1645171200","strike64x64":"55340232221128654848000","contractSize":"100000000000000000","isCall":false,"maxCost":"12288749964831000000"},"contractAddress":"0xa4492fcDa2520cB68657d220f4D4aE3116359C10","contractType":"customAbi"},"transaction":{"status":"pending","monitorId":"Geth_1_C_PROD","monitorVersion":"0.108.0","pendingTimeStamp":"2022-02-05T05:32:53.837Z","pendingBlockNumber":14144116,"hash":"0xd63c3f04c0f85f6bb5402644bbb09290148318c5acb9e9f17d0773e9a7492101","from":"0x1FF60C59246A7b6B4A5090218881Af7f844458b0","to":"0xa4492fcDa2520cB68657d220f4D4aE3116359C10","value":"0","gas":1003494,"nonce":708,"blockHash":null,"blockNumber":null,"v":"0x1","r":"0xacd250a48251b0a83e3d6fa5653c780aa67f838a338dbbcf06c09ddefe1aef35","s":"0x6b376e7ec18fcf302b87729b9155f9a078a70b0cc27b980ecf0249b0b0042f1f","input":"0x677956f100000000000000000000000000000000000000000000000000000000620f5200000000000000000000000000000000000000000000000bb80000000000000000000000000000000000000000000000000000000000000000016345785d8a00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000aa8a68fc035885c0","type":2,"maxFeePerGas":"5000000000","maxFeePerGasGwei":5,"maxPriorityFeePerGas":"1410000000","maxPriorityFeePerGasGwei":1.41,"asset":"ETH","estimatedBlocksUntilConfirmed":null,"watchedAddress":"0xa4492fcda2520cb68657d220f4d4ae3116359c10","direction":"incoming","counterparty":"0x1FF60C59246A7b6B4A5090218881Af7f844458b0"}},"dispatchTimestamp":"2022-02-05T05:32:53.849Z"}"#;
         let resp: Response = serde_json::from_str(json).unwrap();
     }
+
+    #[test]
+    fn test_decode_lenient() {
+        let json: Value = serde_json::from_str(r#"{"version":0,"serverVersion":"0.127.0","timeStamp":"2022-02-05T05:32:53.837Z","connectionId":"c","status":"ok","priority":"high","event":{"timeStamp":"2022-02-05T05:32:53.837Z","categoryCode":"activeAddress","eventCode":"txPool","dappId":"d","blockchain":{"system":"ethereum","network":"main"},"contractCall":{"methodName":"purchase","params":{},"contractAddress":"0xa4492fcDa2520cB68657d220f4D4aE3116359C10","contractType":"customAbi"},"transaction":{"hash":"0x01","gas":"lots"}}}"#).unwrap();
+        assert!(Response::deserialize(&json).is_err());
+
+        let resp = Response::from_value_lenient(json.clone()).unwrap();
+        assert_eq!(resp.extra["priority"], "high");
+        let event = resp.event.unwrap();
+        assert_eq!(event.contract_call.unwrap().method_name, "purchase");
+        assert!(event.transaction.is_none());
+        assert_eq!(event.extra["transaction"]["gas"], "lots");
+
+        let mut broken = json;
+        broken["event"]["eventCode"] = Value::Null;
+        let resp = Response::from_value_lenient(broken).unwrap();
+        assert!(resp.event.is_none());
+        assert_eq!(resp.extra["event"]["categoryCode"], "activeAddress");
+
+        assert!(Response::from_value_lenient(serde_json::json!({"status": "ok"})).is_err());
+    }
+
+    #[test]
+    fn test_net_balance_changes() {
+        let fixture = std::fs::read_to_string("tests/fixtures/quickswap.json").unwrap();
+        let mut json = serde_json::from_str::<Vec<Value>>(&fixture)
+            .unwrap()
+            .remove(0);
+        let simulation: Value = serde_json::from_str(
+            &std::fs::read_to_string("tests/fixtures/simulation.json").unwrap(),
+        )
+        .unwrap();
+        let trader = "0x21F3bB63e775ccDf0CC04559Be142971D241aB0E";
+        let mut changes = simulation["netBalanceChanges"].clone();
+        changes.as_array_mut().unwrap().push(serde_json::json!({
+            "address": trader.to_lowercase(),
+            "balanceChanges": [
+                {
+                    "delta": "-1000000",
+                    "asset": {"type": "erc20", "symbol": "USDC", "contractAddress": "0x2791bca1f2de4661ed88a30c99a7a9449aa84174", "decimals": 6}
+                },
+                {
+                    "delta": "-21000000000000",
+                    "asset": {"type": "ether", "symbol": "MATIC"}
+                }
+            ]
+        }));
+        json["event"]["transaction"]["netBalanceChanges"] = changes;
+        json["event"]["transaction"]["simDetails"] = serde_json::json!({"blockNumber": 22235980, "performanceProfile": {"breakdown": [{"label": "detected", "timeStamp": "2021-12-07T10:20:25.100Z"}], "e2eMs": 96}});
+
+        let tx = Response::deserialize(&json)
+            .unwrap()
+            .event
+            .unwrap()
+            .transaction
+            .unwrap();
+        let sim_details = tx.sim_details.as_ref().unwrap();
+        assert_eq!(sim_details.block_number, 22235980);
+        assert_eq!(
+            sim_details.performance_profile.as_ref().unwrap().e2e_ms,
+            Some(96)
+        );
+
+        let by_address = tx.balance_changes_by_address().unwrap();
+        assert_eq!(by_address.len(), 1);
+        let trader = &by_address[&trader.to_lowercase()];
+        let deltas: Vec<(&str, &str)> = trader
+            .iter()
+            .map(|change| (change.asset.symbol.as_str(), change.delta.as_str()))
+            .collect();
+        assert_eq!(
+            deltas,
+            vec![
+                ("USDC", "-5001000000"),
+                ("USDT", "4995412771"),
+                ("MATIC", "-21000000000000")
+            ]
+        );
+        assert_eq!(trader[0].breakdown.len(), 1);
+        assert_eq!(trader[1].asset.decimals, Some(6));
+    }
+
+    #[test]
+    fn aggregates_deltas_beyond_i128() {
+        let change = |delta: &str| {
+            serde_json::from_value::<NetBalanceChange>(serde_json::json!({
+                "address": "0x21F3bB63e775ccDf0CC04559Be142971D241aB0E",
+                "balanceChanges": [
+                    {"delta": delta, "asset": {"type": "ether", "symbol": "MATIC"}}
+                ]
+            }))
+            .unwrap()
+        };
+        let sum = |deltas: &[&str]| {
+            let changes: Vec<_> = deltas.iter().map(|delta| change(delta)).collect();
+            let by_address = NetBalanceChange::aggregate(&changes)?;
+            let merged = by_address.into_values().next().unwrap();
+            assert_eq!(merged.len(), 1);
+            Ok(merged[0].delta.clone())
+        };
+
+        assert_eq!(
+            sum(&["170141183460469231731687303715884105727", "1"]),
+            Ok("170141183460469231731687303715884105728".to_string())
+        );
+        assert_eq!(sum(&["+0042"]), Ok("42".to_string()));
+        assert_eq!(sum(&["-007", "+005"]), Ok("-2".to_string()));
+        assert_eq!(sum(&["1", "1e3"]), Err(InvalidDelta("1e3".to_string())));
+    }
 }
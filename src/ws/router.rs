@@ -0,0 +1,746 @@
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::{debug, warn};
+
+use super::{
+    buffer::{self, Push},
+    models::{CategoryCode, EventCode, ReplyEvent, Response, WatchConfig},
+    ws::ClientError,
+};
+
+pub(crate) type Sink = buffer::Sender<Result<Response, ClientError>>;
+
+/// Which incoming events a subscription receives.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Route {
+    /// Events whose watched address or called contract is this address
+    Address(String),
+    /// Events of this watched account, stored lowercase
+    Account(String),
+    /// Lifecycle events of this transaction hash and of its replacements
+    Transaction(String),
+    /// Events of the global config, which are not about a watched address
+    Global,
+}
+
+impl Route {
+    /// `lineage` is the hash of the event's transaction followed by the hashes
+    /// it replaced, see `Router::lineage`
+    pub fn matches(&self, resp: &Response, lineage: &[String]) -> bool {
+        let event = match &resp.event {
+            Some(event) => event,
+            None => return false,
+        };
+
+        let watched = event
+            .transaction
+            .as_ref()
+            .and_then(|tx| tx.watch_info.as_ref())
+            .map(|info| info.watched_address.as_str());
+
+        match self {
+            // events without a watched address belong to the global config
+            Route::Address(address) => watched.is_some_and(|watched| {
+                watched.eq_ignore_ascii_case(address)
+                    || event
+                        .contract_call
+                        .as_ref()
+                        .is_some_and(|call| call.contract_address.eq_ignore_ascii_case(address))
+            }),
+            Route::Account(address) => {
+                watched.is_some_and(|watched| watched.eq_ignore_ascii_case(address))
+            }
+            Route::Transaction(hash) => lineage
+                .iter()
+                .any(|ancestor| ancestor.eq_ignore_ascii_case(hash)),
+            Route::Global => {
+                watched.is_none()
+                    && event.transaction.is_some()
+                    && event.category_code != CategoryCode::ActiveTransaction
+            }
+        }
+    }
+
+    /// The route the request echoed in a reply registered
+    pub fn of_reply(event: &ReplyEvent) -> Option<Route> {
+        match event.category_code {
+            CategoryCode::Configs => event.config.as_ref().map(|config| {
+                if config.scope == WatchConfig::GLOBAL_SCOPE {
+                    Route::Global
+                } else {
+                    Route::Address(config.scope.clone())
+                }
+            }),
+            CategoryCode::ActiveTransaction => event
+                .transaction
+                .as_ref()
+                .map(|tx| Route::Transaction(tx.hash.clone())),
+            CategoryCode::AccountAddress => event
+                .account
+                .as_ref()
+                .map(|account| Route::Account(account.address.to_lowercase())),
+            _ => None,
+        }
+    }
+
+    /// Whether both routes stand for the same registration on the server
+    fn is_same(&self, other: &Route) -> bool {
+        match (self, other) {
+            (Route::Address(a), Route::Address(b))
+            | (Route::Account(a), Route::Account(b))
+            | (Route::Transaction(a), Route::Transaction(b)) => a.eq_ignore_ascii_case(b),
+            (Route::Global, Route::Global) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether `resp` is the last event this route will ever match.
+    /// `superseded` tells whether the event's transaction has been replaced.
+    pub fn completes(&self, resp: &Response, superseded: bool) -> bool {
+        let event = match (self, &resp.event) {
+            (Route::Transaction(_), Some(event)) => event,
+            _ => return false,
+        };
+        match &event.event_code {
+            // a mined transaction uses up the nonce, whichever hash it was
+            EventCode::TxConfirmed | EventCode::TxFailed => true,
+            // the lifecycle goes on with the replacement
+            EventCode::TxSpeedUp | EventCode::TxCancel => false,
+            code => code.is_final() && !superseded,
+        }
+    }
+}
+
+/// A registered subscription.
+pub(crate) struct Entry {
+    pub routes: Vec<Route>,
+    /// Messages which registered the subscription, replayed after a reconnect
+    pub requests: Vec<String>,
+    /// Messages which remove the subscription on the server, the one at each
+    /// index for the route at the same index
+    pub unsubscribe: Vec<String>,
+    pub sink: Sink,
+}
+
+/// Outcome of `Router::dispatch`
+#[derive(Default)]
+pub(crate) struct Dispatched {
+    /// Number of subscriptions which received the response
+    pub delivered: usize,
+    /// Subscriptions disconnected for falling behind, still registered on the server
+    pub lagged: Vec<Entry>,
+    /// Subscriptions whose receiver was dropped, still registered on the server
+    pub closed: Vec<Entry>,
+}
+
+/// Fans incoming responses out to the subscriptions they belong to.
+#[derive(Default)]
+pub(crate) struct Router {
+    subscriptions: BTreeMap<u64, Entry>,
+    /// Speed ups and cancellations of watched transactions, the lowercase hash
+    /// of each replacement to the hash it replaced
+    replacements: BTreeMap<String, String>,
+}
+
+impl Router {
+    pub fn insert(&mut self, id: u64, entry: Entry) -> Option<Entry> {
+        self.subscriptions.insert(id, entry)
+    }
+
+    pub fn remove(&mut self, id: u64) -> Option<Entry> {
+        let entry = self.subscriptions.remove(&id);
+        self.prune();
+        entry
+    }
+
+    /// Unsubscribe messages of `entry`, a removed subscription, for the routes
+    /// no other subscription listens on. The server keeps a single registration
+    /// per scope, which must outlive every stream sharing it.
+    pub fn orphaned(&self, entry: &Entry) -> Vec<String> {
+        let mut released: Vec<&Route> = vec![];
+        let mut orphaned = vec![];
+        for (route, request) in entry.routes.iter().zip(&entry.unsubscribe) {
+            let shared = released.iter().any(|other| other.is_same(route))
+                || self
+                    .subscriptions
+                    .values()
+                    .flat_map(|other| other.routes.iter())
+                    .any(|other| other.is_same(route));
+            if !shared {
+                orphaned.push(request.clone());
+            }
+            released.push(route);
+        }
+        orphaned
+    }
+
+    /// Removes and returns every subscription listening on `route`
+    pub fn remove_route(&mut self, route: &Route) -> Vec<Entry> {
+        let ids: Vec<u64> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, entry)| entry.routes.iter().any(|other| other.is_same(route)))
+            .map(|(id, _)| *id)
+            .collect();
+        let removed = ids
+            .into_iter()
+            .filter_map(|id| self.subscriptions.remove(&id))
+            .collect();
+        self.prune();
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// Registration messages of every live subscription
+    pub fn requests(&self) -> impl Iterator<Item = &String> {
+        self.subscriptions
+            .values()
+            .flat_map(|entry| entry.requests.iter())
+    }
+
+    /// Delivers `resp` to every matching subscription, dropping the ones whose
+    /// receiver has gone away or which have seen their final event. Waits for
+    /// room in subscriptions whose buffer blocks on overflow.
+    pub async fn dispatch(&mut self, resp: &Response) -> Dispatched {
+        let tx = resp
+            .event
+            .as_ref()
+            .and_then(|event| event.transaction.as_ref());
+        if let Some((original, replacement)) = tx.and_then(|tx| tx.replacement()) {
+            self.link(original, replacement);
+        }
+        let lineage = tx.map(|tx| self.lineage(&tx.hash)).unwrap_or_default();
+        let superseded = lineage
+            .first()
+            .is_some_and(|hash| self.replacements.values().any(|original| original == hash));
+
+        let mut dispatched = Dispatched::default();
+        let mut removed = false;
+        let ids: Vec<u64> = self.subscriptions.keys().copied().collect();
+        for id in ids {
+            let entry = &self.subscriptions[&id];
+            let route = match entry
+                .routes
+                .iter()
+                .find(|route| route.matches(resp, &lineage))
+            {
+                Some(route) => route,
+                None => continue,
+            };
+
+            let mut push = entry.sink.try_push(Ok(resp.clone()));
+            while let Push::Full(resp) = push {
+                entry.sink.ready().await;
+                push = entry.sink.try_push(resp);
+            }
+
+            let keep = match push {
+                Push::Queued => {
+                    dispatched.delivered += 1;
+                    !route.completes(resp, superseded)
+                }
+                Push::Dropped => {
+                    debug!("subscription {} is full, dropped event", id);
+                    true
+                }
+                Push::Full(_) => unreachable!("retried until there is room"),
+                Push::Closed if entry.sink.is_lagging() => {
+                    warn!("subscription {} fell behind, disconnecting", id);
+                    if let Some(entry) = self.subscriptions.remove(&id) {
+                        dispatched.lagged.push(entry);
+                    }
+                    removed = true;
+                    continue;
+                }
+                Push::Closed => {
+                    debug!("subscription {} receiver dropped", id);
+                    if let Some(entry) = self.subscriptions.remove(&id) {
+                        dispatched.closed.push(entry);
+                    }
+                    removed = true;
+                    continue;
+                }
+            };
+            if !keep {
+                self.subscriptions.remove(&id);
+                removed = true;
+            }
+        }
+        if removed {
+            self.prune();
+        }
+        dispatched
+    }
+
+    /// Removes every subscription, ending each stream with the error `error`
+    /// builds for it
+    pub fn fail(&mut self, error: impl Fn() -> ClientError) {
+        for entry in std::mem::take(&mut self.subscriptions).into_values() {
+            entry.sink.finish(Err(error()));
+        }
+        self.replacements.clear();
+    }
+
+    /// `hash` followed by the hashes it replaced, most recent first, all
+    /// lowercase
+    fn lineage(&self, hash: &str) -> Vec<String> {
+        let mut lineage = vec![hash.to_lowercase()];
+        while let Some(original) = lineage.last().and_then(|hash| self.replacements.get(hash)) {
+            if lineage.contains(original) {
+                warn!("replacements of {} form a cycle", hash);
+                break;
+            }
+            lineage.push(original.clone());
+        }
+        lineage
+    }
+
+    /// Records that `replacement` replaces `original`, if a subscription
+    /// follows `original`
+    fn link(&mut self, original: &str, replacement: &str) {
+        let watched = self.watched();
+        if self
+            .lineage(original)
+            .iter()
+            .any(|hash| watched.contains(hash))
+        {
+            debug!("{} replaces {}", replacement, original);
+            self.replacements
+                .insert(replacement.to_lowercase(), original.to_lowercase());
+        }
+    }
+
+    /// Forgets the replacements no subscription follows anymore
+    fn prune(&mut self) {
+        if self.replacements.is_empty() {
+            return;
+        }
+        let watched = self.watched();
+        let stale: Vec<String> = self
+            .replacements
+            .keys()
+            .filter(|hash| !self.lineage(hash).iter().any(|hash| watched.contains(hash)))
+            .cloned()
+            .collect();
+        for hash in stale {
+            self.replacements.remove(&hash);
+        }
+    }
+
+    /// Lowercase hashes of the transaction routes
+    fn watched(&self) -> BTreeSet<String> {
+        self.subscriptions
+            .values()
+            .flat_map(|entry| entry.routes.iter())
+            .filter_map(|route| match route {
+                Route::Transaction(hash) => Some(hash.to_lowercase()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::{
+        models::EventCode,
+        ws::{BufferConfig, Overflow},
+    };
+    use futures_util::StreamExt;
+
+    fn channel() -> (Sink, buffer::Receiver<Result<Response, ClientError>>) {
+        buffer::channel(BufferConfig::default())
+    }
+
+    fn entry(routes: Vec<Route>, unsubscribe: Vec<String>, sink: Sink) -> Entry {
+        Entry {
+            routes,
+            requests: vec![],
+            unsubscribe,
+            sink,
+        }
+    }
+
+    fn response(watched: &str, contract: &str, hash: &str) -> Response {
+        event("txPool", watched, contract, hash)
+    }
+
+    fn event(code: &str, watched: &str, contract: &str, hash: &str) -> Response {
+        let json = format!(
+            r#"{{"version":0,"serverVersion":"0.127.0","timeStamp":"2022-02-05T05:32:53.837Z","connectionId":"c","status":"ok","event":{{"timeStamp":"2022-02-05T05:32:53.837Z","categoryCode":"activeAddress","eventCode":"{code}","dappId":"d","blockchain":{{"system":"ethereum","network":"matic-main"}},"contractCall":{{"contractType":"Uniswap V2: Router 2","contractAddress":"{contract}","methodName":"swapExactTokensForTokens","params":{{}}}},"transaction":{{"status":"pending","monitorId":"m","monitorVersion":"0.1","pendingTimeStamp":"2022-02-05T05:32:53.837Z","pendingBlockNumber":1,"hash":"{hash}","from":"0x1","to":"{contract}","value":"0","gas":1,"nonce":1,"v":"0x1","r":"0x1","s":"0x1","input":"0x","gasPrice":"1","asset":"","watchedAddress":"{watched}","direction":"incoming","counterparty":"0x1"}}}}}}"#
+        );
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn routes_by_address() {
+        let mut router = Router::default();
+        let (a_sink, a_stream) = channel();
+        let (b_sink, b_stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Address("0xaaaa".into())], vec![], a_sink),
+        );
+        router.insert(
+            2,
+            entry(vec![Route::Address("0xbbbb".into())], vec![], b_sink),
+        );
+
+        assert_eq!(
+            router
+                .dispatch(&response("0xAAAA", "0xcccc", "0x01"))
+                .await
+                .delivered,
+            1
+        );
+        assert_eq!(
+            router
+                .dispatch(&response("0xdddd", "0xBBBB", "0x02"))
+                .await
+                .delivered,
+            1
+        );
+        assert_eq!(
+            router
+                .dispatch(&response("0xdddd", "0xeeee", "0x03"))
+                .await
+                .delivered,
+            0
+        );
+        drop(router);
+
+        let a: Vec<_> = a_stream.collect().await;
+        let b: Vec<_> = b_stream.collect().await;
+        assert_eq!(a.len(), 1);
+        assert_eq!(b.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn transaction_route_ends_on_final_event() {
+        let mut router = Router::default();
+        let (sink, stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Transaction("0xFEED".into())], vec![], sink),
+        );
+
+        assert_eq!(
+            router
+                .dispatch(&event("txPool", "0x1", "0x2", "0x03"))
+                .await
+                .delivered,
+            0
+        );
+        assert_eq!(
+            router
+                .dispatch(&event("txPool", "0x1", "0x2", "0xfeed"))
+                .await
+                .delivered,
+            1
+        );
+        assert!(!router.is_empty());
+        assert_eq!(
+            router
+                .dispatch(&event("txConfirmed", "0x1", "0x2", "0xfeed"))
+                .await
+                .delivered,
+            1
+        );
+        assert!(router.is_empty());
+
+        let codes: Vec<_> = stream
+            .map(|resp| resp.unwrap().event.unwrap().event_code)
+            .collect()
+            .await;
+        assert_eq!(codes, vec![EventCode::TxPool, EventCode::TxConfirmed]);
+    }
+
+    #[tokio::test]
+    async fn transaction_route_follows_replacements() {
+        let mut router = Router::default();
+        let (sink, stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Transaction("0xAAAA".into())], vec![], sink),
+        );
+        let replacing = |code: &str, hash: &str, original: &str| {
+            let mut resp = event(code, "0x1", "0x2", hash);
+            let tx = resp.event.as_mut().unwrap().transaction.as_mut().unwrap();
+            tx.original_hash = Some(original.to_string());
+            resp
+        };
+
+        let events = vec![
+            event("txPool", "0x1", "0x2", "0xaaaa"),
+            replacing("txSpeedUp", "0xBBBB", "0xaaaa"),
+            event("txPool", "0x1", "0x2", "0xdddd"),
+            replacing("txCancel", "0xcccc", "0xbbbb"),
+            // the original leaving the mempool does not end the lifecycle
+            event("txDropped", "0x1", "0x2", "0xaaaa"),
+        ];
+        let mut delivered = vec![];
+        for resp in &events {
+            delivered.push(router.dispatch(resp).await.delivered);
+        }
+        assert_eq!(delivered, vec![1, 1, 0, 1, 1]);
+        assert_eq!(router.replacements.len(), 2);
+        assert!(!router.is_empty());
+
+        assert_eq!(
+            router
+                .dispatch(&event("txConfirmed", "0x1", "0x2", "0xcccc"))
+                .await
+                .delivered,
+            1
+        );
+        assert!(router.is_empty());
+        assert!(router.replacements.is_empty());
+
+        let hashes: Vec<_> = stream
+            .map(|resp| {
+                let event = resp.unwrap().event.unwrap();
+                (event.event_code, event.transaction.unwrap().hash)
+            })
+            .collect()
+            .await;
+        assert_eq!(
+            hashes.last().unwrap(),
+            &(EventCode::TxConfirmed, "0xcccc".to_string())
+        );
+        assert_eq!(hashes.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn transaction_route_follows_replace_hash() {
+        let mut router = Router::default();
+        let (sink, stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Transaction("0xaaaa".into())], vec![], sink),
+        );
+        let mut speed_up = event("txSpeedUp", "0x1", "0x2", "0xaaaa");
+        speed_up
+            .event
+            .as_mut()
+            .unwrap()
+            .transaction
+            .as_mut()
+            .unwrap()
+            .replace_hash = Some("0xBBBB".to_string());
+
+        let events = vec![
+            speed_up,
+            event("txPool", "0x1", "0x2", "0xbbbb"),
+            event("txConfirmed", "0x1", "0x2", "0xBBBB"),
+        ];
+        let mut delivered = vec![];
+        for resp in &events {
+            delivered.push(router.dispatch(resp).await.delivered);
+        }
+        assert_eq!(delivered, vec![1, 1, 1]);
+        assert!(router.is_empty());
+        assert!(router.replacements.is_empty());
+
+        let lifecycle: Vec<_> = stream
+            .map(|resp| {
+                let event = resp.unwrap().event.unwrap();
+                (event.event_code, event.transaction.unwrap().hash)
+            })
+            .collect()
+            .await;
+        assert_eq!(
+            lifecycle,
+            vec![
+                (EventCode::TxSpeedUp, "0xaaaa".to_string()),
+                (EventCode::TxPool, "0xbbbb".to_string()),
+                (EventCode::TxConfirmed, "0xBBBB".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_replacements_nobody_follows() {
+        let mut router = Router::default();
+        let (sink, _stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Address("0xaaaa".into())], vec![], sink),
+        );
+
+        let mut resp = event("txSpeedUp", "0xaaaa", "0x2", "0x02");
+        resp.event
+            .as_mut()
+            .unwrap()
+            .transaction
+            .as_mut()
+            .unwrap()
+            .replace_hash = Some("0x03".to_string());
+        assert_eq!(router.dispatch(&resp).await.delivered, 1);
+        assert!(router.replacements.is_empty());
+    }
+
+    #[tokio::test]
+    async fn account_route_ignores_contract_calls() {
+        let mut router = Router::default();
+        let (sink, _stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Account("0xaaaa".into())], vec![], sink),
+        );
+
+        assert_eq!(
+            router
+                .dispatch(&response("0xdddd", "0xAAAA", "0x01"))
+                .await
+                .delivered,
+            0
+        );
+        assert_eq!(
+            router
+                .dispatch(&response("0xAAAA", "0xdddd", "0x02"))
+                .await
+                .delivered,
+            1
+        );
+        assert!(router.remove(1).is_some());
+        assert!(router.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsubscribes_last_subscription_of_scope() {
+        let mut router = Router::default();
+        let scoped = |scope: &str| {
+            entry(
+                vec![Route::Address(scope.into()), Route::Global],
+                vec![format!("delete {}", scope), "delete global".into()],
+                channel().0,
+            )
+        };
+        router.insert(1, scoped("0xaaaa"));
+        router.insert(2, scoped("0xAAAA"));
+        router.insert(3, scoped("0xbbbb"));
+
+        let first = router.remove(1).unwrap();
+        assert!(router.orphaned(&first).is_empty());
+        let second = router.remove(2).unwrap();
+        assert_eq!(router.orphaned(&second), vec!["delete 0xAAAA".to_string()]);
+        let third = router.remove(3).unwrap();
+        assert_eq!(
+            router.orphaned(&third),
+            vec!["delete 0xbbbb".to_string(), "delete global".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_closed_subscriptions() {
+        let mut router = Router::default();
+        let (sink, stream) = channel();
+        router.insert(
+            1,
+            entry(
+                vec![Route::Address("0xaaaa".into())],
+                vec!["delete 0xaaaa".into()],
+                sink,
+            ),
+        );
+        drop(stream);
+
+        // the event arrives before the unsubscribe the dropped stream queued,
+        // which then finds nothing to remove
+        let dispatched = router.dispatch(&response("0xaaaa", "0xcccc", "0x01")).await;
+        assert_eq!(dispatched.delivered, 0);
+        assert!(router.is_empty());
+        assert!(router.remove(1).is_none());
+        assert_eq!(dispatched.closed.len(), 1);
+        assert_eq!(
+            router.orphaned(&dispatched.closed[0]),
+            vec!["delete 0xaaaa".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn hands_back_lagging_subscriptions() {
+        let mut router = Router::default();
+        let (sink, stream) = buffer::channel(BufferConfig {
+            capacity: 1,
+            overflow: Overflow::Disconnect,
+        });
+        router.insert(
+            1,
+            entry(
+                vec![Route::Address("0xaaaa".into())],
+                vec!["unwatch".into()],
+                sink,
+            ),
+        );
+
+        let first = router.dispatch(&response("0xaaaa", "0xcccc", "0x01")).await;
+        assert_eq!(first.delivered, 1);
+        let second = router.dispatch(&response("0xaaaa", "0xcccc", "0x02")).await;
+        assert_eq!(second.delivered, 0);
+        assert_eq!(second.lagged.len(), 1);
+        assert_eq!(second.lagged[0].unsubscribe, vec!["unwatch".to_string()]);
+        assert!(router.is_empty());
+
+        assert_eq!(stream.dropped(), 1);
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn global_route_is_separate() {
+        let mut router = Router::default();
+        let (address_sink, address_stream) = channel();
+        let (global_sink, global_stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Address("0xaaaa".into())], vec![], address_sink),
+        );
+        router.insert(2, entry(vec![Route::Global], vec![], global_sink));
+
+        let mut global = response("", "0xaaaa", "0x02");
+        if let Some(tx) = global.event.as_mut().and_then(|e| e.transaction.as_mut()) {
+            tx.watch_info = None;
+        }
+
+        assert_eq!(
+            router
+                .dispatch(&response("0xaaaa", "0xcccc", "0x01"))
+                .await
+                .delivered,
+            1
+        );
+        assert_eq!(router.dispatch(&global).await.delivered, 1);
+        drop(router);
+
+        let hashes = |resps: Vec<Result<Response, ClientError>>| -> Vec<String> {
+            resps
+                .into_iter()
+                .map(|resp| resp.unwrap().event.unwrap().transaction.unwrap().hash)
+                .collect()
+        };
+        assert_eq!(hashes(address_stream.collect().await), vec!["0x01"]);
+        assert_eq!(hashes(global_stream.collect().await), vec!["0x02"]);
+    }
+
+    #[tokio::test]
+    async fn fail_ends_every_stream() {
+        let mut router = Router::default();
+        let (sink, stream) = channel();
+        router.insert(
+            1,
+            entry(vec![Route::Address("0xaaaa".into())], vec![], sink),
+        );
+        router.dispatch(&response("0xaaaa", "0xcccc", "0x01")).await;
+
+        router.fail(|| ClientError::UnexpectedClose);
+        assert!(router.is_empty());
+
+        let items: Vec<_> = stream.collect().await;
+        assert_eq!(items.len(), 2);
+        assert!(items[0].is_ok());
+        assert!(matches!(items[1], Err(ClientError::UnexpectedClose)));
+    }
+}
@@ -0,0 +1,424 @@
+//! Numeric view of the wire models, available with the `ethers` feature.
+//!
+//! The server sends amounts as decimal strings and addresses, hashes and calldata
+//! as hex strings. The types here parse them once into ethers primitives so they
+//! can be compared and used in arithmetic.
+use ethers::{
+    types::{Address, Bytes, H256, U256, U64},
+    utils::to_checksum,
+};
+use std::{fmt, ops::Deref, str::FromStr};
+use thiserror::Error;
+
+use super::models::{self, TransactionStatus};
+use crate::models::Blockchain;
+
+/// Error thrown when a field of a server message is not a valid number, address,
+/// hash or hex string
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("invalid `{field}` value {value:?}: {reason}")]
+pub struct ConversionError {
+    pub field: &'static str,
+    pub value: String,
+    pub reason: String,
+}
+
+impl ConversionError {
+    pub(crate) fn new(field: &'static str, value: &str, reason: impl ToString) -> Self {
+        Self {
+            field,
+            value: value.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// An address which displays with its EIP-55 checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct ChecksumAddress(pub Address);
+
+impl Deref for ChecksumAddress {
+    type Target = Address;
+
+    fn deref(&self) -> &Address {
+        &self.0
+    }
+}
+
+impl From<Address> for ChecksumAddress {
+    fn from(address: Address) -> Self {
+        Self(address)
+    }
+}
+
+impl From<ChecksumAddress> for Address {
+    fn from(address: ChecksumAddress) -> Self {
+        address.0
+    }
+}
+
+impl fmt::Display for ChecksumAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_checksum(&self.0, None))
+    }
+}
+
+pub(crate) fn parse_address(
+    field: &'static str,
+    value: &str,
+) -> Result<ChecksumAddress, ConversionError> {
+    Address::from_str(value)
+        .map(ChecksumAddress)
+        .map_err(|e| ConversionError::new(field, value, e))
+}
+
+/// Empty strings stand for a missing address, e.g. `to` of a contract creation
+pub(crate) fn parse_optional_address(
+    field: &'static str,
+    value: &str,
+) -> Result<Option<ChecksumAddress>, ConversionError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    parse_address(field, value).map(Some)
+}
+
+pub(crate) fn parse_hash(field: &'static str, value: &str) -> Result<H256, ConversionError> {
+    H256::from_str(value).map_err(|e| ConversionError::new(field, value, e))
+}
+
+/// Accepts decimal amounts as well as `0x` prefixed hex quantities
+pub(crate) fn parse_u256(field: &'static str, value: &str) -> Result<U256, ConversionError> {
+    match value.strip_prefix("0x") {
+        Some(hex) => U256::from_str(hex).map_err(|e| ConversionError::new(field, value, e)),
+        None => U256::from_dec_str(value).map_err(|e| ConversionError::new(field, value, e)),
+    }
+}
+
+pub(crate) fn parse_u64(field: &'static str, value: &str) -> Result<U64, ConversionError> {
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed
+        .map(U64::from)
+        .map_err(|e| ConversionError::new(field, value, e))
+}
+
+/// Block numbers and indices are sent as JSON numbers
+pub(crate) fn to_u64(field: &'static str, value: i64) -> Result<U64, ConversionError> {
+    u64::try_from(value)
+        .map(U64::from)
+        .map_err(|e| ConversionError::new(field, &value.to_string(), e))
+}
+
+pub(crate) fn parse_bytes(field: &'static str, value: &str) -> Result<Bytes, ConversionError> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value))
+        .map(Bytes::from)
+        .map_err(|e| ConversionError::new(field, value, e))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GasInfo {
+    Eip1559 {
+        base_fee_per_gas: Option<U256>,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    },
+    Legacy {
+        gas_price: U256,
+    },
+}
+
+impl TryFrom<&models::GasInfo> for GasInfo {
+    type Error = ConversionError;
+
+    fn try_from(info: &models::GasInfo) -> Result<Self, Self::Error> {
+        Ok(match info {
+            models::GasInfo::ERC1559 {
+                base_fee_per_gas,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+            } => GasInfo::Eip1559 {
+                base_fee_per_gas: base_fee_per_gas
+                    .as_deref()
+                    .map(|fee| parse_u256("baseFeePerGas", fee))
+                    .transpose()?,
+                max_fee_per_gas: parse_u256("maxFeePerGas", max_fee_per_gas)?,
+                max_priority_fee_per_gas: parse_u256(
+                    "maxPriorityFeePerGas",
+                    max_priority_fee_per_gas,
+                )?,
+            },
+            models::GasInfo::Legacy { gas_price } => GasInfo::Legacy {
+                gas_price: parse_u256("gasPrice", gas_price)?,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfirmedInfo {
+    pub block_hash: H256,
+    pub block_number: U64,
+    pub transaction_index: U64,
+    pub gas_used: U256,
+}
+
+impl TryFrom<&models::ConfirmedInfo> for ConfirmedInfo {
+    type Error = ConversionError;
+
+    fn try_from(info: &models::ConfirmedInfo) -> Result<Self, Self::Error> {
+        Ok(Self {
+            block_hash: parse_hash("blockHash", &info.block_hash)?,
+            block_number: to_u64("blockNumber", info.block_number)?,
+            transaction_index: to_u64("transactionIndex", info.transaction_index)?,
+            gas_used: parse_u256("gasUsed", &info.gas_used)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InternalTransaction {
+    DelegateCall(InternalTransactionDetails),
+    Call(InternalTransactionDetails),
+    StaticCall(InternalTransactionDetails),
+    Create(InternalTransactionDetails),
+    Create2(InternalTransactionDetails),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InternalTransactionDetails {
+    pub from: ChecksumAddress,
+    pub to: ChecksumAddress,
+    pub input: Bytes,
+    pub gas: u64,
+    pub gas_used: u64,
+    pub value: U256,
+}
+
+impl TryFrom<&models::InternalTransaction> for InternalTransaction {
+    type Error = ConversionError;
+
+    fn try_from(tx: &models::InternalTransaction) -> Result<Self, Self::Error> {
+        Ok(match tx {
+            models::InternalTransaction::DelegateCall(details) => {
+                InternalTransaction::DelegateCall(details.try_into()?)
+            }
+            models::InternalTransaction::Call(details) => {
+                InternalTransaction::Call(details.try_into()?)
+            }
+            models::InternalTransaction::StaticCall(details) => {
+                InternalTransaction::StaticCall(details.try_into()?)
+            }
+            models::InternalTransaction::Create(details) => {
+                InternalTransaction::Create(details.try_into()?)
+            }
+            models::InternalTransaction::Create2(details) => {
+                InternalTransaction::Create2(details.try_into()?)
+            }
+        })
+    }
+}
+
+impl TryFrom<&models::InternalTransactionDetails> for InternalTransactionDetails {
+    type Error = ConversionError;
+
+    fn try_from(details: &models::InternalTransactionDetails) -> Result<Self, Self::Error> {
+        Ok(Self {
+            from: parse_address("from", &details.from)?,
+            to: parse_address("to", &details.to)?,
+            input: parse_bytes("input", &details.input)?,
+            gas: details.gas,
+            gas_used: details.gas_used,
+            value: parse_u256("value", &details.value)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub status: TransactionStatus,
+    pub hash: H256,
+    pub from: ChecksumAddress,
+    /// `None` for contract creations
+    pub to: Option<ChecksumAddress>,
+    pub value: U256,
+    pub gas: u64,
+    pub nonce: u64,
+    pub v: U64,
+    pub r: U256,
+    pub s: U256,
+    pub input: Bytes,
+    pub gas_info: GasInfo,
+    pub transaction_type: Option<U64>,
+    pub confirmed: Option<ConfirmedInfo>,
+    pub internal_transactions: Vec<InternalTransaction>,
+}
+
+impl TryFrom<&models::Transaction> for Transaction {
+    type Error = ConversionError;
+
+    fn try_from(tx: &models::Transaction) -> Result<Self, Self::Error> {
+        Ok(Self {
+            status: tx.status.clone(),
+            hash: parse_hash("hash", &tx.hash)?,
+            from: parse_address("from", &tx.from)?,
+            to: parse_optional_address("to", &tx.to)?,
+            value: parse_u256("value", &tx.value)?,
+            gas: tx.gas,
+            nonce: tx.nonce,
+            v: parse_u64("v", &tx.v)?,
+            r: parse_u256("r", &tx.r)?,
+            s: parse_u256("s", &tx.s)?,
+            input: parse_bytes("input", &tx.input)?,
+            gas_info: (&tx.gas_info).try_into()?,
+            transaction_type: tx.type_field.map(|kind| to_u64("type", kind)).transpose()?,
+            confirmed: tx.confirmed.as_ref().map(TryInto::try_into).transpose()?,
+            internal_transactions: tx
+                .internal_transactions
+                .iter()
+                .flatten()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl models::Transaction {
+    /// Parses amounts, addresses, hashes and calldata into ethers types
+    pub fn typed(&self) -> Result<Transaction, ConversionError> {
+        self.try_into()
+    }
+
+    /// Converts into an ethers transaction, with the chain id of `blockchain`
+    pub fn to_ethers(
+        &self,
+        blockchain: &Blockchain,
+    ) -> Result<ethers::types::Transaction, ConversionError> {
+        let mut tx = ethers::types::Transaction::from(self.typed()?);
+        tx.chain_id = blockchain.chain_id().map(U256::from);
+        Ok(tx)
+    }
+}
+
+impl From<Transaction> for ethers::types::Transaction {
+    fn from(tx: Transaction) -> Self {
+        let (gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match tx.gas_info {
+            GasInfo::Legacy { gas_price } => (Some(gas_price), None, None),
+            GasInfo::Eip1559 {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                ..
+            } => (None, Some(max_fee_per_gas), Some(max_priority_fee_per_gas)),
+        };
+
+        ethers::types::Transaction {
+            hash: tx.hash,
+            nonce: tx.nonce.into(),
+            block_hash: tx.confirmed.as_ref().map(|info| info.block_hash),
+            block_number: tx.confirmed.as_ref().map(|info| info.block_number),
+            transaction_index: tx.confirmed.as_ref().map(|info| info.transaction_index),
+            from: tx.from.0,
+            to: tx.to.map(Address::from),
+            value: tx.value,
+            gas_price,
+            gas: tx.gas.into(),
+            input: tx.input,
+            v: tx.v,
+            r: tx.r,
+            s: tx.s,
+            transaction_type: tx.transaction_type,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            ..Default::default()
+        }
+    }
+}
+
+/// Fails with a `ConversionError` naming the malformed field, the chain id is
+/// left unset (see `Transaction::to_ethers`)
+impl TryFrom<models::Transaction> for ethers::types::Transaction {
+    type Error = ConversionError;
+
+    fn try_from(tx: models::Transaction) -> Result<Self, Self::Error> {
+        Ok(tx.typed()?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_amounts() {
+        assert_eq!(parse_u256("value", "1000").unwrap(), U256::from(1000));
+        assert_eq!(parse_u256("value", "0x3e8").unwrap(), U256::from(1000));
+        assert_eq!(parse_u64("v", "0x136").unwrap(), U64::from(310));
+        assert!(parse_u256("value", "ten").is_err());
+    }
+
+    #[test]
+    fn displays_checksum_address() {
+        let address = parse_address("to", "0xa5e0829caced8ffdd4de3c43696c57f7d7a678ff").unwrap();
+        assert_eq!(
+            address.to_string(),
+            "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff"
+        );
+        assert_eq!(parse_optional_address("to", "").unwrap(), None);
+    }
+
+    #[test]
+    fn typed_transaction() {
+        let json = r#"{"status":"confirmed","monitorId":"Geth_137_C_PROD","monitorVersion":"0.102.0","timePending":"3146","blocksPending":3,"pendingTimeStamp":"2021-12-07T10:20:22.066Z","pendingBlockNumber":22235980,"hash":"0xe0b1cf2bea578f49ba78cacd0d12d9c013f07cdd987936e71965edf6bd972b78","from":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E","to":"0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff","value":"1000000000000000000","gas":387473,"nonce":45,"blockHash":"0xa814777d863e89c2b565ad4947e37e48bc5d8407b4065303c6371de519980d89","blockNumber":22235983,"v":"0x136","r":"0xb1fa90713d69a05869823607cc4bc67de6c7d4599b9fe8b00c54d8bc902739f9","s":"0x297a6aba5a47be29475d037b41619ad4003048e82305f20a3b18927cbfe2a343","input":"0x38ed1739","gasPrice":"113000000000","gasPriceGwei":113,"gasUsed":"236672","transactionIndex":1,"asset":"","blockTimeStamp":"2021-12-07T10:20:25.000Z","watchedAddress":"0xa5e0829caced8ffdd4de3c43696c57f7d7a678ff","direction":"incoming","counterparty":"0x21F3bB63e775ccDf0CC04559Be142971D241aB0E"}"#;
+        let tx: models::Transaction = serde_json::from_str(json).unwrap();
+        let typed = tx.typed().unwrap();
+
+        assert_eq!(typed.value, U256::exp10(18));
+        assert_eq!(typed.input, Bytes::from(vec![0x38, 0xed, 0x17, 0x39]));
+        assert_eq!(
+            typed.gas_info,
+            GasInfo::Legacy {
+                gas_price: U256::from(113_000_000_000u64)
+            }
+        );
+        let confirmed = typed.confirmed.unwrap();
+        assert_eq!(confirmed.block_number, U64::from(22235983));
+        assert_eq!(confirmed.gas_used, U256::from(236672));
+
+        let ethers_tx = tx.to_ethers(&Blockchain::polygon()).unwrap();
+        assert_eq!(ethers_tx.chain_id, Some(U256::from(137)));
+        assert_eq!(ethers_tx.gas_price, Some(U256::from(113_000_000_000u64)));
+        assert_eq!(ethers_tx.block_number, Some(U64::from(22235983)));
+        assert_eq!(ethers_tx.transaction_index, Some(U64::from(1)));
+        assert_eq!(ethers_tx.max_fee_per_gas, None);
+    }
+
+    #[test]
+    fn ethers_transaction_eip1559() {
+        let json = r#"{"status":"pending","monitorId":"Geth_1_D_PROD","monitorVersion":"0.108.0","pendingTimeStamp":"2022-02-05T00:14:59.317Z","pendingBlockNumber":14142762,"hash":"0xebc639e7f6bd7a3c3d8ad5a58f805fa8e024c0308ee784179a7fb8716859e095","from":"0x38Ab1C0e1c3a185594792F7FD7212Eeb563F044C","to":"0x9011F2133A705Fe72226647B5B246086C6b72140","value":"0","gas":461111,"nonce":53,"blockHash":null,"blockNumber":null,"v":"0x0","r":"0xfc3419eb5467401484773f1449af01e606e2a49db885e189b72ddcee4f100969","s":"0x2f5106ee50c3f13169d9b13b8cae0faaae116a640e17c169a187451e731a0015","input":"0x4585e33b","type":2,"maxFeePerGas":"200638563976","maxFeePerGasGwei":201,"maxPriorityFeePerGas":"2500000000","maxPriorityFeePerGasGwei":2.5,"asset":"ETH"}"#;
+        let tx: models::Transaction = serde_json::from_str(json).unwrap();
+        let ethers_tx = ethers::types::Transaction::try_from(tx).unwrap();
+
+        assert_eq!(ethers_tx.transaction_type, Some(U64::from(2)));
+        assert_eq!(ethers_tx.gas_price, None);
+        assert_eq!(
+            ethers_tx.max_fee_per_gas,
+            Some(U256::from(200_638_563_976u64))
+        );
+        assert_eq!(
+            ethers_tx.max_priority_fee_per_gas,
+            Some(U256::from(2_500_000_000u64))
+        );
+        assert_eq!(ethers_tx.block_hash, None);
+        assert_eq!(ethers_tx.chain_id, None);
+    }
+
+    #[test]
+    fn ethers_transaction_reports_bad_field() {
+        let json = r#"{"status":"pending","monitorId":"m","monitorVersion":"0.1","hash":"0xebc6","from":"0x38Ab1C0e1c3a185594792F7FD7212Eeb563F044C","to":"0x9011F2133A705Fe72226647B5B246086C6b72140","value":"0","gas":1,"nonce":1,"v":"0x0","r":"0x1","s":"0x1","input":"0x","gasPrice":"1","asset":"ETH"}"#;
+        let tx: models::Transaction = serde_json::from_str(json).unwrap();
+        let err = ethers::types::Transaction::try_from(tx).unwrap_err();
+        assert_eq!(err.field, "hash");
+    }
+}
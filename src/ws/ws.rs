@@ -1,47 +1,100 @@
 use futures_channel::{mpsc, oneshot};
 use futures_util::{
+    future::{self, BoxFuture},
     sink::{Sink, SinkExt},
     stream::{Fuse, Stream, StreamExt},
+    FutureExt,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{btree_map::Entry, BTreeMap},
     fmt::{self, Debug},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
 };
 use thiserror::Error;
+use tokio::{sync::watch, time::Instant};
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{self, protocol::CloseFrame},
+    tungstenite::{
+        self, client::IntoClientRequest, handshake::client::Request as ClientRequest,
+        protocol::CloseFrame,
+    },
 };
 
-use super::models::{
-    HelloMsg, JsonRpcError, Request, Response, TransactionSubscribe, WatchConfig, WatchRequest,
+use super::{
+    abi::{self, AbiError},
+    buffer::{self, Push},
+    filter::FilterError,
+    models::{
+        AccountEvent, AccountSubscribe, CategoryCode, EventCode, HelloMsg, JsonRpcError, Reply,
+        Request, Response, Status, TransactionSubscribe, UnwatchRequest, WatchConfig, WatchRequest,
+    },
+    router::{Entry, Route, Router, Sink as Subscription},
 };
 use crate::models::Blockchain;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
+
+/// A request waiting for the server to acknowledge or reject it. The server
+/// echoes the request in its reply, which is matched on the codes and `key`.
+#[derive(Debug)]
+struct Pending {
+    category: CategoryCode,
+    event: EventCode,
+    /// Scope, hash or address of the request
+    key: Option<String>,
+    sender: oneshot::Sender<Result<Reply, ClientError>>,
+}
 
-type Pending = oneshot::Sender<Result<serde_json::Value, JsonRpcError>>;
-type Subscription = mpsc::UnboundedSender<Response>;
+impl Pending {
+    fn matches(&self, reply: &Reply) -> bool {
+        let event = match &reply.event {
+            Some(event) => event,
+            // connection level errors such as a bad api key are not echoed
+            None => {
+                return reply.status == Status::Error && self.category == CategoryCode::Initialize
+            }
+        };
+
+        let key = match (&self.key, event.key()) {
+            (Some(ours), Some(theirs)) => ours.eq_ignore_ascii_case(theirs),
+            _ => true,
+        };
+        self.category == event.category_code && self.event == event.event_code && key
+    }
+}
 
 type Message = tungstenite::protocol::Message;
 type WsError = tungstenite::Error;
 type WsStreamItem = Result<Message, WsError>;
 
+/// Opens a fresh websocket connection, used by the `WsServer` to reconnect.
+type Connector<S> = Box<dyn Fn() -> BoxFuture<'static, Result<S, ClientError>> + Send + Sync>;
+
 /// Instructions for the `WsServer`.
 #[derive(Debug)]
 enum Instruction {
-    // Send keepalive
-    Ping,
-    /// JSON-RPC request
-    Request {
-        request: String,
-    },
-    /// Create a new subscription
+    /// `checkDappId` request, which is sent now and replayed first after every
+    /// reconnect
+    Handshake { request: String },
+    /// Create a new subscription, sending (and replaying) its `requests`
     Subscribe {
+        id: u64,
+        routes: Vec<Route>,
+        requests: Vec<String>,
+        unsubscribe: Vec<String>,
         sink: Subscription,
     },
-    /// Cancel an existing subscription
-    Unsubscribe,
+    /// Cancel an existing subscription and remove it on the server
+    Unsubscribe { id: u64 },
+    /// Wait for the server's reply to a request sent afterwards
+    Expect { pending: Pending },
+    /// Forward messages no subscription can take to `sink`
+    Raw { sink: buffer::Sender<RawMessage> },
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -51,12 +104,179 @@ enum Incoming {
     Response(Response),
 }
 
+/// Backoff applied between reconnection attempts after the socket drops.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Maximum number of consecutive attempts, `None` retries forever
+    pub max_attempts: Option<u32>,
+    /// Delay before the first attempt
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after every failed attempt
+    pub multiplier: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: None,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Never reconnect, streams end when the socket drops
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// Delay before the given (1-based) attempt
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .checked_pow(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        self.initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+/// Keepalive pings the client sends to detect dead connections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    /// Delay between pings
+    pub interval: Duration,
+    /// How long to wait for the pong before the connection counts as dead
+    pub timeout: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+/// What a subscription does with new events while its buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Stop reading the socket until the consumer catches up. This stalls the
+    /// whole connection: every other subscription, pending requests and the
+    /// heartbeat wait too, so a consumer slower than the pong timeout forces
+    /// a reconnect
+    Block,
+    /// Discard the oldest buffered event to make room
+    #[default]
+    DropOldest,
+    /// Discard the new event
+    DropNewest,
+    /// End the stream once the buffered events are read and remove the
+    /// subscription on the server
+    Disconnect,
+}
+
+/// How the client decodes events which do not fit the models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decode {
+    /// Forward them to `Ws::raw_messages` only
+    #[default]
+    Strict,
+    /// Deliver them to subscriptions as far as they decode, see
+    /// `Response::from_value_lenient`
+    Lenient,
+}
+
+/// Size and overflow policy of the buffer behind each subscription stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferConfig {
+    /// Maximum number of undelivered events, at least 1
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// Status of the connection behind a `Ws`.
+#[derive(Debug, Clone)]
+pub enum ConnectionState {
+    /// Opening the socket
+    Connecting,
+    /// The socket is open, waiting for the server's hello and for it to
+    /// accept the api key
+    Handshaking,
+    /// Requests and events flow
+    Ready,
+    /// The socket dropped, waiting before the given (1-based) attempt to
+    /// reopen it
+    Reconnecting { attempt: u32 },
+    /// The connection is gone for good. `reason` is the error which ended it,
+    /// `None` if every handle and stream was dropped.
+    Closed { reason: Option<Arc<ClientError>> },
+}
+
+impl ConnectionState {
+    pub fn is_ready(&self) -> bool {
+        matches!(self, ConnectionState::Ready)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self, ConnectionState::Closed { .. })
+    }
+}
+
+/// Options for `Ws::connect_with_config`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsConfig {
+    pub reconnect: ReconnectPolicy,
+    /// How long to wait for the server to acknowledge a request
+    pub request_timeout: Duration,
+    /// Buffer of new subscriptions, see `Ws::with_buffer`
+    pub buffer: BufferConfig,
+    /// Keepalive pings, `None` relies on the server to notice dead connections
+    pub heartbeat: Option<Heartbeat>,
+    pub decode: Decode,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            reconnect: ReconnectPolicy::default(),
+            request_timeout: Duration::from_secs(30),
+            buffer: BufferConfig::default(),
+            heartbeat: Some(Heartbeat::default()),
+            decode: Decode::default(),
+        }
+    }
+}
+
 /// Client over Websockets.
 #[derive(Clone)]
 pub struct Ws {
     instructions: mpsc::UnboundedSender<Instruction>,
     api_key: String,
     blockchain: Blockchain,
+    next_id: Arc<AtomicU64>,
+    request_timeout: Duration,
+    buffer: BufferConfig,
+    hello: watch::Receiver<Option<HelloMsg>>,
+    state: watch::Receiver<ConnectionState>,
 }
 
 impl Debug for Ws {
@@ -72,40 +292,144 @@ impl Ws {
     where
         S: Send + Sync + Stream<Item = WsStreamItem> + Sink<Message, Error = WsError> + Unpin,
     {
-        let (sink, stream) = mpsc::unbounded();
+        let config = WsConfig {
+            reconnect: ReconnectPolicy::disabled(),
+            ..Default::default()
+        };
+        Self::spawn(ws, None, config, api_key, blockchain)
+    }
 
-        let mut ping_sink = sink.clone();
-        tokio::task::spawn(async move {
-            loop {
-                ping_sink.send(Instruction::Ping).await.unwrap();
-                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
-            }
-        });
+    fn spawn<S>(
+        ws: S,
+        connector: Option<Connector<S>>,
+        config: WsConfig,
+        api_key: &str,
+        blockchain: Blockchain,
+    ) -> Self
+    where
+        S: 'static
+            + Send
+            + Sync
+            + Stream<Item = WsStreamItem>
+            + Sink<Message, Error = WsError>
+            + Unpin,
+    {
+        let (sink, stream) = mpsc::unbounded();
+        let (hello_sink, hello) = watch::channel(None);
+        let (state_sink, state) = watch::channel(ConnectionState::Handshaking);
 
         // Spawn the server
-        WsServer::new(ws, stream).spawn();
+        WsServer::new(ws, stream, hello_sink, state_sink, connector, &config).spawn();
 
         Self {
             blockchain,
             instructions: sink,
             api_key: api_key.to_string(),
+            next_id: Arc::new(AtomicU64::new(1)),
+            request_timeout: config.request_timeout,
+            buffer: config.buffer,
+            hello,
+            state,
         }
     }
 
-    /// Returns true if the WS connection is active, false otherwise
+    /// Returns true if the WS connection is open and past the handshake
     pub fn ready(&self) -> bool {
-        !self.instructions.is_closed()
+        self.state.borrow().is_ready()
+    }
+
+    /// Current status of the connection
+    pub fn state(&self) -> ConnectionState {
+        self.state.borrow().clone()
+    }
+
+    /// Receiver which is notified whenever the connection status changes, e.g.
+    /// to wait for `ConnectionState::Ready` after a reconnect
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// Id the server assigned to the current connection, once it greeted us
+    pub fn connection_id(&self) -> Option<String> {
+        self.hello
+            .borrow()
+            .as_ref()
+            .map(|hello| hello.connection_id.clone())
+    }
+
+    /// Returns a handle whose new subscriptions use `buffer` instead of the
+    /// connection's default. Both handles share the connection.
+    pub fn with_buffer(&self, buffer: BufferConfig) -> Self {
+        Self {
+            buffer,
+            ..self.clone()
+        }
+    }
+
+    /// The greeting of the current connection, once the server has sent it
+    pub fn hello(&self) -> Option<HelloMsg> {
+        self.hello.borrow().clone()
+    }
+
+    /// Sends `checkDappId` and waits for both the server's hello and its verdict
+    /// on the api key. The request is replayed after every reconnect.
+    ///
+    /// Fails with `ClientError::Rejected` and the server's reason, e.g. for an
+    /// unknown or expired api key or an unsupported network, if the server
+    /// refuses the connection.
+    pub async fn handshake(&self) -> Result<HelloMsg, ClientError> {
+        let reply = self.expect(CategoryCode::Initialize, EventCode::CheckDappId, None)?;
+        self.send(Instruction::Handshake {
+            request: self.request(CategoryCode::Initialize, EventCode::CheckDappId, ())?,
+        })?;
+
+        let mut hello = self.hello.clone();
+        let greeting = async {
+            loop {
+                if let Some(msg) = hello.borrow().clone() {
+                    return Ok::<_, ClientError>(msg);
+                }
+                hello
+                    .changed()
+                    .await
+                    .map_err(|_| ClientError::UnexpectedClose)?;
+            }
+        };
+        let greeting = tokio::time::timeout(self.request_timeout, greeting)
+            .await
+            .map_err(|_| ClientError::RequestTimeout)??;
+
+        self.acknowledged(vec![reply]).await?;
+        Ok(greeting)
     }
 
     /// Initializes a new WebSocket Client
     pub async fn connect(
-        url: impl tungstenite::client::IntoClientRequest + Unpin,
+        url: impl IntoClientRequest + Unpin,
+        api_key: &str,
+        blockchain: Blockchain,
+    ) -> Result<Self, ClientError> {
+        Self::connect_with_config(url, api_key, blockchain, WsConfig::default()).await
+    }
+
+    /// Initializes a new WebSocket Client which reconnects according to `config`,
+    /// replaying the handshake and every registered subscription.
+    pub async fn connect_with_config(
+        url: impl IntoClientRequest + Unpin,
         api_key: &str,
         blockchain: Blockchain,
+        config: WsConfig,
     ) -> Result<Self, ClientError> {
-        let (ws, _) = connect_async(url).await?;
-        let me = Self::new(ws, api_key, blockchain);
-        me.cast("initialize", "checkDappId", ()).await.unwrap();
+        let request = url.into_client_request()?;
+        let (ws, _) = connect_async(clone_request(&request)).await?;
+        let connector: Connector<_> = Box::new(move || {
+            connect_async(clone_request(&request))
+                .map(|res| res.map(|(ws, _)| ws).map_err(ClientError::from))
+                .boxed()
+        });
+
+        let me = Self::spawn(ws, Some(connector), config, api_key, blockchain);
+        me.handshake().await?;
         Ok(me)
     }
 
@@ -115,68 +439,371 @@ impl Ws {
             .map_err(to_client_error)
     }
 
-    // type Error = ClientError;
-    async fn cast<T: Serialize + Send + Sync>(
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn request<T: Serialize>(
         &self,
-        method: &str,
-        code: &str,
+        method: CategoryCode,
+        code: EventCode,
         params: T,
-    ) -> Result<(), ClientError> {
-        // send the message
-        let payload = Instruction::Request {
-            request: serde_json::to_string(&Request::new(
-                &self.api_key.to_string(),
-                self.blockchain.clone(),
-                method,
-                code,
-                params,
-            ))?,
-        };
+    ) -> Result<String, ClientError> {
+        Ok(serde_json::to_string(&Request::new(
+            &self.api_key,
+            self.blockchain.clone(),
+            method,
+            code,
+            params,
+        ))?)
+    }
 
-        // send the data
-        self.send(payload)?;
+    /// Registers interest in the server's reply to the next `category`/`event`
+    /// request about `key`. Must be called before the request is sent.
+    fn expect(
+        &self,
+        category: CategoryCode,
+        event: EventCode,
+        key: Option<String>,
+    ) -> Result<oneshot::Receiver<Result<Reply, ClientError>>, ClientError> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(Instruction::Expect {
+            pending: Pending {
+                category,
+                event,
+                key,
+                sender,
+            },
+        })?;
+        Ok(receiver)
+    }
 
+    /// Waits for the server to acknowledge each request, failing on the first
+    /// rejection
+    async fn acknowledged(
+        &self,
+        replies: Vec<oneshot::Receiver<Result<Reply, ClientError>>>,
+    ) -> Result<(), ClientError> {
+        for reply in replies {
+            tokio::time::timeout(self.request_timeout, reply)
+                .await
+                .map_err(|_| ClientError::RequestTimeout)???;
+        }
         Ok(())
     }
 }
-pub type NotificationStream = mpsc::UnboundedReceiver<Response>;
 
-impl Ws {
-    pub async fn subscribe(&self, config: WatchConfig) -> Result<NotificationStream, ClientError> {
-        let (sink, stream) = mpsc::unbounded();
+/// `http::Request` is not `Clone`, copy the parts tungstenite looks at.
+fn clone_request(request: &ClientRequest) -> ClientRequest {
+    let mut clone = ClientRequest::new(());
+    *clone.method_mut() = request.method().clone();
+    *clone.uri_mut() = request.uri().clone();
+    *clone.version_mut() = request.version();
+    *clone.headers_mut() = request.headers().clone();
+    clone
+}
 
-        tracing::info!("Subscribing to filter on scope: {}", config.scope);
+/// Stream of the events of one subscription.
+///
+/// If the connection fails for good, e.g. because reconnecting gave up, the
+/// stream yields a last `ClientError::ConnectionClosed` and ends.
+///
+/// Dropping the stream (or calling `unsubscribe`) detaches it from the client,
+/// and removes the subscription on the server once no other stream shares its
+/// scope, hash or address.
+#[derive(Debug)]
+pub struct NotificationStream {
+    id: u64,
+    inner: buffer::Receiver<Result<Response, ClientError>>,
+    instructions: mpsc::UnboundedSender<Instruction>,
+    subscribed: bool,
+}
 
-        let req = WatchRequest { config };
+impl NotificationStream {
+    /// Client side id of the subscription
+    pub fn id(&self) -> u64 {
+        self.id
+    }
 
-        // cast configs message and subscribe
-        self.cast("configs", "put", req).await.unwrap();
-        self.send(Instruction::Subscribe { sink })?;
+    /// Number of events discarded because the stream was not read fast enough
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
 
-        Ok(stream)
+    /// Removes the subscription on the server and ends the stream
+    pub fn unsubscribe(mut self) -> Result<(), ClientError> {
+        self.subscribed = false;
+        self.instructions
+            .unbounded_send(Instruction::Unsubscribe { id: self.id })
+            .map_err(to_client_error)
+    }
+}
+
+impl Stream for NotificationStream {
+    type Item = Result<Response, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// Stream of the transactions of one watched account.
+#[derive(Debug)]
+pub struct AccountStream {
+    inner: NotificationStream,
+}
+
+impl AccountStream {
+    /// Client side id of the subscription
+    pub fn id(&self) -> u64 {
+        self.inner.id()
+    }
+
+    /// Number of events discarded because the stream was not read fast enough
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    /// Stops watching the account and ends the stream
+    pub fn unsubscribe(self) -> Result<(), ClientError> {
+        self.inner.unsubscribe()
+    }
+}
+
+impl Stream for AccountStream {
+    type Item = Result<AccountEvent, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match futures_util::ready!(self.inner.poll_next_unpin(cx)) {
+                Some(Ok(resp)) => {
+                    if let Some(event) = AccountEvent::from_response(resp) {
+                        return Poll::Ready(Some(Ok(event)));
+                    }
+                }
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        if self.subscribed {
+            // the server may already be gone, nothing left to clean up then
+            let _ = self
+                .instructions
+                .unbounded_send(Instruction::Unsubscribe { id: self.id });
+        }
+    }
+}
+
+/// A message from the server which no subscription received.
+#[derive(Debug)]
+pub struct RawMessage {
+    pub text: String,
+    /// Why the message did not decode, `None` for responses which carry `raw`
+    /// data instead of an event
+    pub error: Option<serde_json::Error>,
+}
+
+/// Stream of the messages the client could not decode, see `Ws::raw_messages`.
+#[derive(Debug)]
+pub struct RawStream {
+    inner: buffer::Receiver<RawMessage>,
+}
+
+impl RawStream {
+    /// Number of messages discarded because the stream was not read fast enough
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+}
+
+impl Stream for RawStream {
+    type Item = RawMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
     }
+}
 
+impl Ws {
+    /// Registers `config` and returns a stream of the events on its scope, or of
+    /// the unscoped events for `WatchConfig::global`. Fails with
+    /// `ClientError::InvalidAbi` or `ClientError::InvalidFilter` if the ABI is
+    /// malformed or a filter can never match, and with `ClientError::Rejected`
+    /// if the server refuses the config.
+    pub async fn subscribe(&self, config: WatchConfig) -> Result<NotificationStream, ClientError> {
+        self.subscribe_many(vec![config]).await
+    }
+
+    /// Registers all `configs` and returns a single stream of the events on their
+    /// scopes. Fails if the server refuses any of them.
     pub async fn subscribe_many(
         &self,
         configs: Vec<WatchConfig>,
     ) -> Result<NotificationStream, ClientError> {
-        let (sink, stream) = mpsc::unbounded();
-
+        let mut routes = Vec::with_capacity(configs.len());
+        let mut requests = Vec::with_capacity(configs.len());
+        let mut unsubscribe = Vec::with_capacity(configs.len());
+        let mut replies = Vec::with_capacity(configs.len());
+
+        for config in &configs {
+            abi::validate(&config.abi)?;
+            config.validate()?;
+        }
         for config in configs {
-            tracing::info!("Subscribe to filter on scope: {}", config.scope);
-
-            let req = WatchRequest { config };
-
-            // cast configs message and subscribe
-            self.cast("configs", "put", req).await.unwrap();
-            self.send(Instruction::Subscribe { sink: sink.clone() });
+            tracing::info!("Subscribing to filter on scope: {}", config.scope);
+
+            replies.push(self.expect(
+                CategoryCode::Configs,
+                EventCode::Put,
+                Some(config.scope.clone()),
+            )?);
+            routes.push(if config.is_global() {
+                Route::Global
+            } else {
+                Route::Address(config.scope.clone())
+            });
+            unsubscribe.push(self.request(
+                CategoryCode::Configs,
+                EventCode::Delete,
+                UnwatchRequest::new(config.scope.clone()),
+            )?);
+            requests.push(self.request(
+                CategoryCode::Configs,
+                EventCode::Put,
+                WatchRequest { config },
+            )?);
         }
 
+        // dropping the stream on failure removes whatever the server did accept
+        let stream = self.open(routes, requests, unsubscribe)?;
+        self.acknowledged(replies).await?;
         Ok(stream)
     }
 
+    /// Watches a single transaction hash, failing with `ClientError::Rejected`
+    /// if the server refuses it. The stream yields its lifecycle events
+    /// (`txPool`, `txConfirmed`, `txSpeedUp`, ...) and ends once it reaches a final
+    /// state. Speed ups and cancellations are followed, so the events of the
+    /// replacements arrive on the same stream, which ends with the outcome of
+    /// whichever transaction is mined.
+    pub async fn watch_transaction(
+        &self,
+        hash: impl Into<String>,
+    ) -> Result<NotificationStream, ClientError> {
+        let hash = hash.into();
+        tracing::info!("Watching transaction: {}", hash);
+
+        let request = self.request(
+            CategoryCode::ActiveTransaction,
+            EventCode::TxSent,
+            TransactionSubscribe::new(hash.clone()),
+        )?;
+        let unsubscribe = self.request(
+            CategoryCode::ActiveTransaction,
+            EventCode::Unwatch,
+            TransactionSubscribe::new(hash.clone()),
+        )?;
+        let reply = self.expect(
+            CategoryCode::ActiveTransaction,
+            EventCode::TxSent,
+            Some(hash.clone()),
+        )?;
+
+        let stream = self.open(
+            vec![Route::Transaction(hash)],
+            vec![request],
+            vec![unsubscribe],
+        )?;
+        self.acknowledged(vec![reply]).await?;
+        Ok(stream)
+    }
+
+    /// Watches an account address, failing with `ClientError::Rejected` if the
+    /// server refuses it. The stream yields its incoming and outgoing
+    /// transactions.
+    pub async fn watch_account(
+        &self,
+        address: impl Into<String>,
+    ) -> Result<AccountStream, ClientError> {
+        let address = address.into();
+        tracing::info!("Watching account: {}", address);
+
+        let request = self.request(
+            CategoryCode::AccountAddress,
+            EventCode::Watch,
+            AccountSubscribe::account(address.clone()),
+        )?;
+        let unsubscribe = self.request(
+            CategoryCode::AccountAddress,
+            EventCode::Unwatch,
+            AccountSubscribe::account(address.clone()),
+        )?;
+
+        let reply = self.expect(
+            CategoryCode::AccountAddress,
+            EventCode::Watch,
+            Some(address.clone()),
+        )?;
+
+        let inner = self.open(
+            vec![Route::Account(address.to_lowercase())],
+            vec![request],
+            vec![unsubscribe],
+        )?;
+        self.acknowledged(vec![reply]).await?;
+        Ok(AccountStream { inner })
+    }
+
+    /// Ends `stream`. The server stops watching its address once no other
+    /// `AccountStream` watches it.
+    pub async fn unwatch_account(&self, stream: AccountStream) -> Result<(), ClientError> {
+        stream.unsubscribe()
+    }
+
+    /// Removes the subscription with the given id on the server and ends its stream
     pub async fn unsubscribe<T: Into<u64>>(&self, id: T) -> Result<(), ClientError> {
-        self.send(Instruction::Unsubscribe)
+        self.send(Instruction::Unsubscribe { id: id.into() })
+    }
+
+    /// Returns a stream of the messages which are otherwise logged and dropped:
+    /// those which are not valid JSON or do not fit the models, and responses
+    /// with `raw` data. Only the latest stream receives messages, opening a new
+    /// one ends the previous.
+    pub fn raw_messages(&self) -> Result<RawStream, ClientError> {
+        let (sink, inner) = buffer::channel(self.buffer);
+        self.send(Instruction::Raw { sink })?;
+        Ok(RawStream { inner })
+    }
+
+    /// Registers a subscription with the server task and hands out its stream
+    fn open(
+        &self,
+        routes: Vec<Route>,
+        requests: Vec<String>,
+        unsubscribe: Vec<String>,
+    ) -> Result<NotificationStream, ClientError> {
+        let (sink, inner) = buffer::channel(self.buffer);
+        let id = self.next_id();
+
+        self.send(Instruction::Subscribe {
+            id,
+            routes,
+            requests,
+            unsubscribe,
+            sink,
+        })?;
+
+        Ok(NotificationStream {
+            id,
+            inner,
+            instructions: self.instructions.clone(),
+            subscribed: true,
+        })
     }
 }
 
@@ -184,7 +811,21 @@ struct WsServer<S> {
     ws: Fuse<S>,
     instructions: Fuse<mpsc::UnboundedReceiver<Instruction>>,
     pending: Vec<Pending>,
-    subscription: Option<Subscription>,
+    subscriptions: Router,
+    /// `checkDappId` request, replayed first after a reconnect
+    handshake: Option<String>,
+    /// Receiver of undecodable messages
+    raw: Option<buffer::Sender<RawMessage>>,
+    decode: Decode,
+    /// Greeting of the current connection, with the latest connection id
+    hello: watch::Sender<Option<HelloMsg>>,
+    state: watch::Sender<ConnectionState>,
+    connector: Option<Connector<S>>,
+    reconnect: ReconnectPolicy,
+    heartbeat: Option<Heartbeat>,
+    /// When the next ping is due, or the pong of the last one
+    next_beat: Instant,
+    awaiting_pong: bool,
 }
 
 impl<S> WsServer<S>
@@ -192,15 +833,34 @@ where
     S: Send + Sync + Stream<Item = WsStreamItem> + Sink<Message, Error = WsError> + Unpin,
 {
     /// Instantiates the Websocket Server
-    fn new(ws: S, requests: mpsc::UnboundedReceiver<Instruction>) -> Self {
-        Self {
+    fn new(
+        ws: S,
+        requests: mpsc::UnboundedReceiver<Instruction>,
+        hello: watch::Sender<Option<HelloMsg>>,
+        state: watch::Sender<ConnectionState>,
+        connector: Option<Connector<S>>,
+        config: &WsConfig,
+    ) -> Self {
+        let mut server = Self {
             // Fuse the 2 steams together, so that we can `select` them in the
             // Stream implementation
             ws: ws.fuse(),
             instructions: requests.fuse(),
             pending: Vec::default(),
-            subscription: None,
-        }
+            subscriptions: Router::default(),
+            handshake: None,
+            raw: None,
+            decode: config.decode,
+            hello,
+            state,
+            connector,
+            reconnect: config.reconnect.clone(),
+            heartbeat: config.heartbeat.clone(),
+            next_beat: Instant::now(),
+            awaiting_pong: false,
+        };
+        server.reset_heartbeat();
+        server
     }
 
     /// Returns whether the all work has been completed.
@@ -208,7 +868,12 @@ where
     /// If this method returns `true`, then the `instructions` channel has been closed and all
     /// pending requests and subscriptions have been completed.
     fn is_done(&self) -> bool {
-        self.instructions.is_done() && self.pending.is_empty() && self.subscription.is_none()
+        self.instructions.is_done()
+            && self
+                .pending
+                .iter()
+                .all(|pending| pending.sender.is_canceled())
+            && self.subscriptions.is_empty()
     }
 
     /// Spawns the event loop
@@ -217,80 +882,265 @@ where
         S: 'static,
     {
         let f = async move {
-            loop {
+            let reason = loop {
                 if self.is_done() {
                     debug!("work complete");
-                    break;
+                    break None;
                 }
                 match self.tick().await {
-                    Err(ClientError::UnexpectedClose) => {
-                        error!("{}", ClientError::UnexpectedClose);
-                        break;
-                    }
-                    Err(e) => {
-                        panic!("WS Server panic: {}", e);
+                    Ok(()) => {}
+                    Err(e) if e.is_disconnect() && self.can_reconnect() => {
+                        warn!("{}", e);
+                        if let Err(e) = self.reconnect().await {
+                            break Some(e);
+                        }
                     }
-                    _ => {}
+                    Err(e) => break Some(e),
                 }
-            }
+            };
+            self.close(reason);
         };
 
         tokio::spawn(f);
     }
 
+    fn can_reconnect(&self) -> bool {
+        self.connector.is_some() && self.reconnect.max_attempts != Some(0)
+    }
+
+    /// Hands the error which ended the connection to every waiting request and
+    /// subscription, then publishes the final state
+    fn close(&mut self, reason: Option<ClientError>) {
+        let reason = reason.map(|e| {
+            error!("connection closed: {}", e);
+            Arc::new(e)
+        });
+        if let Some(reason) = &reason {
+            let closed = || ClientError::ConnectionClosed(reason.clone());
+            for pending in self.pending.drain(..) {
+                let _ = pending.sender.send(Err(closed()));
+            }
+            self.subscriptions.fail(closed);
+        }
+        self.hello.send_replace(None);
+        self.state.send_replace(ConnectionState::Closed { reason });
+    }
+
+    /// Reopens the connection with backoff and replays every registered request
+    async fn reconnect(&mut self) -> Result<(), ClientError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if matches!(self.reconnect.max_attempts, Some(max) if attempt > max) {
+                return Err(ClientError::ReconnectFailed(attempt - 1));
+            }
+
+            let connect = match &self.connector {
+                Some(connector) => connector(),
+                None => return Err(ClientError::ReconnectFailed(attempt - 1)),
+            };
+
+            self.state
+                .send_replace(ConnectionState::Reconnecting { attempt });
+            tokio::time::sleep(self.reconnect.backoff(attempt)).await;
+            debug!("reconnect attempt {}", attempt);
+
+            self.state.send_replace(ConnectionState::Connecting);
+            match connect.await {
+                Ok(ws) => {
+                    self.ws = ws.fuse();
+                    self.hello.send_replace(None);
+                    self.state.send_replace(ConnectionState::Handshaking);
+                    self.reset_heartbeat();
+                    match self.replay().await {
+                        Ok(()) => {
+                            info!("reconnected after {} attempt(s)", attempt);
+                            return Ok(());
+                        }
+                        Err(e) => warn!("replay after reconnect failed: {}", e),
+                    }
+                }
+                Err(e) => warn!("reconnect attempt {} failed: {}", attempt, e),
+            }
+        }
+    }
+
+    async fn replay(&mut self) -> Result<(), ClientError> {
+        let requests: Vec<String> = self
+            .handshake
+            .iter()
+            .chain(self.subscriptions.requests())
+            .cloned()
+            .collect();
+        for request in requests {
+            self.ws.send(Message::Text(request)).await?;
+        }
+        Ok(())
+    }
+
     // dispatch an RPC request
     async fn service_request(&mut self, request: String) -> Result<(), ClientError> {
         tracing::debug!("Sending to ws: {:#?}", &request);
-        if let Err(e) = self.ws.send(Message::Text(request)).await {
-            error!("WS connection error: {:?}", e);
-            self.pending.pop();
-        }
-
+        self.ws.send(Message::Text(request)).await?;
         Ok(())
     }
 
-    /// Dispatch a subscription request
-    async fn service_ping(&mut self) -> Result<(), ClientError> {
+    /// Dispatch the handshake and remember it for replay
+    async fn service_handshake(&mut self, request: String) -> Result<(), ClientError> {
+        self.handshake = Some(request.clone());
+        self.state.send_replace(ConnectionState::Handshaking);
+        self.service_request(request).await
+    }
+
+    fn reset_heartbeat(&mut self) {
+        self.awaiting_pong = false;
+        if let Some(heartbeat) = &self.heartbeat {
+            self.next_beat = Instant::now() + heartbeat.interval;
+        }
+    }
+
+    /// Sends the next ping, or declares the connection dead if the last one
+    /// went unanswered
+    async fn beat(&mut self) -> Result<(), ClientError> {
+        let timeout = match &self.heartbeat {
+            Some(heartbeat) => heartbeat.timeout,
+            None => return Ok(()),
+        };
+        if self.awaiting_pong {
+            return Err(ClientError::PongTimeout(timeout));
+        }
+
         self.ws.send(Message::Ping(vec![])).await?;
+        self.awaiting_pong = true;
+        self.next_beat = Instant::now() + timeout;
         Ok(())
     }
 
     /// Dispatch a subscription request
-    async fn service_subscribe(&mut self, sink: Subscription) -> Result<(), ClientError> {
-        if self.subscription.is_some() {
-            warn!("Replacing already registered subscription.");
+    async fn service_subscribe(
+        &mut self,
+        id: u64,
+        routes: Vec<Route>,
+        requests: Vec<String>,
+        unsubscribe: Vec<String>,
+        sink: Subscription,
+    ) -> Result<(), ClientError> {
+        for request in &requests {
+            self.service_request(request.clone()).await?;
         }
 
-        self.subscription = Some(sink);
+        let entry = Entry {
+            routes,
+            requests,
+            unsubscribe,
+            sink,
+        };
+        if self.subscriptions.insert(id, entry).is_some() {
+            warn!("Replacing already registered subscription {}.", id);
+        }
 
         Ok(())
     }
 
     /// Dispatch a unsubscribe request
-    async fn service_unsubscribe(&mut self) -> Result<(), ClientError> {
-        if self.subscription.is_none() {
-            warn!("Unsubscribing from non-existent subscription.");
+    async fn service_unsubscribe(&mut self, id: u64) -> Result<(), ClientError> {
+        match self.subscriptions.remove(id) {
+            Some(entry) => {
+                for request in self.subscriptions.orphaned(&entry) {
+                    self.service_request(request).await?;
+                }
+            }
+            // streams which already ended unsubscribe again when dropped
+            None => debug!("Unsubscribing from non-existent subscription {}.", id),
         }
 
-        self.subscription = None;
-
         Ok(())
     }
 
     /// Dispatch an outgoing message
     async fn service(&mut self, instruction: Instruction) -> Result<(), ClientError> {
         match instruction {
-            Instruction::Request {
-                // id,
-                request,
-                // sender,
-            } => self.service_request(request).await,
-            Instruction::Ping => self.service_ping().await,
-            Instruction::Subscribe { sink } => self.service_subscribe(sink).await,
-            Instruction::Unsubscribe => self.service_unsubscribe().await,
+            Instruction::Handshake { request } => self.service_handshake(request).await,
+            Instruction::Subscribe {
+                id,
+                routes,
+                requests,
+                unsubscribe,
+                sink,
+            } => {
+                self.service_subscribe(id, routes, requests, unsubscribe, sink)
+                    .await
+            }
+            Instruction::Unsubscribe { id } => self.service_unsubscribe(id).await,
+            Instruction::Expect { pending } => {
+                self.pending.push(pending);
+                Ok(())
+            }
+            Instruction::Raw { sink } => {
+                self.raw = Some(sink);
+                Ok(())
+            }
         }
     }
 
+    /// Resolves the pending request `reply` answers. A rejection nobody waits
+    /// for, such as a config refused when it is replayed after a reconnect,
+    /// ends the streams of the route it was about.
+    async fn handle_reply(&mut self, reply: Reply) -> Result<(), ClientError> {
+        let event = reply.event.as_ref();
+        if reply.status != Status::Error
+            && event.is_some_and(|event| {
+                event.category_code == CategoryCode::Initialize
+                    && event.event_code == EventCode::CheckDappId
+            })
+        {
+            self.state.send_replace(ConnectionState::Ready);
+        }
+
+        // callers which timed out dropped their receiver
+        self.pending.retain(|pending| !pending.sender.is_canceled());
+        let reason = || {
+            reply
+                .reason
+                .clone()
+                .unwrap_or_else(|| "no reason given".to_string())
+        };
+
+        if let Some(index) = self
+            .pending
+            .iter()
+            .position(|pending| pending.matches(&reply))
+        {
+            let pending = self.pending.remove(index);
+            let result = match reply.status {
+                Status::Error => Err(ClientError::Rejected(reason())),
+                _ => Ok(reply),
+            };
+            let _ = pending.sender.send(result);
+            return Ok(());
+        }
+
+        if reply.status != Status::Error {
+            debug!("acknowledged: {:?}", event);
+            return Ok(());
+        }
+        let route = match event.and_then(Route::of_reply) {
+            Some(route) => route,
+            None => {
+                warn!("server error: {} {:?}", reason(), event);
+                return Ok(());
+            }
+        };
+        for entry in self.subscriptions.remove_route(&route) {
+            warn!("server rejected {:?}: {}", route, reason());
+            entry.sink.finish(Err(ClientError::Rejected(reason())));
+            for request in self.subscriptions.orphaned(&entry) {
+                self.service_request(request).await?;
+            }
+        }
+        Ok(())
+    }
+
     async fn handle_ping(&mut self, inner: Vec<u8>) -> Result<(), ClientError> {
         tracing::debug!("handle ping: {:?}", inner);
         self.ws.send(Message::Pong(inner)).await?;
@@ -299,23 +1149,74 @@ where
 
     async fn handle_text(&mut self, inner: String) -> Result<(), ClientError> {
         tracing::debug!(inner = ?&inner);
-        let inner_dbg = inner.clone();
-        match serde_json::from_str::<Incoming>(&inner) {
+        let value = match serde_json::from_str::<serde_json::Value>(&inner) {
+            Ok(value) => value,
             Err(e) => {
-                tracing::error!(e = ?&e);
-                tracing::error!("inner: {}", inner_dbg);
+                self.forward_raw(inner, Some(e)).await;
+                return Ok(());
             }
-            Ok(Incoming::HelloMsg(_)) => {}
-            Ok(Incoming::Response(resp)) => {
-                if resp.raw.is_none() {
-                    if let Some(stream) = &self.subscription  {
-                        if let Err(err) = stream.unbounded_send(resp) {
-                            if err.is_disconnected() {
-                                self.subscription = None;
-                            }
-
-                            return Err(to_client_error(err));
-                        }
+        };
+
+        if let Some(id) = value.get("connectionId").and_then(|id| id.as_str()) {
+            self.hello.send_if_modified(|hello| match hello {
+                Some(hello) if hello.connection_id != id => {
+                    hello.connection_id = id.to_string();
+                    true
+                }
+                _ => false,
+            });
+        }
+
+        if Reply::is_reply(&value) {
+            return match Reply::deserialize(&value) {
+                Ok(reply) => self.handle_reply(reply).await,
+                Err(e) => {
+                    self.forward_raw(inner, Some(e)).await;
+                    Ok(())
+                }
+            };
+        }
+
+        let incoming = match Incoming::deserialize(&value) {
+            Ok(incoming) => incoming,
+            Err(e) => match self.decode {
+                Decode::Strict => {
+                    self.forward_raw(inner, Some(e)).await;
+                    return Ok(());
+                }
+                Decode::Lenient => match Response::from_value_lenient(value) {
+                    Ok(resp) => Incoming::Response(resp),
+                    Err(_) => {
+                        self.forward_raw(inner, Some(e)).await;
+                        return Ok(());
+                    }
+                },
+            },
+        };
+
+        match incoming {
+            Incoming::HelloMsg(hello) => {
+                info!(
+                    "connected to server {} as {}",
+                    hello.server_version, hello.connection_id
+                );
+                self.hello.send_replace(Some(hello));
+                // without a handshake there is nothing else to wait for
+                if self.handshake.is_none() {
+                    self.state.send_replace(ConnectionState::Ready);
+                }
+            }
+            Incoming::Response(resp) if resp.raw.is_some() => {
+                self.forward_raw(inner, None).await;
+            }
+            Incoming::Response(resp) => {
+                let dispatched = self.subscriptions.dispatch(&resp).await;
+                if dispatched.delivered == 0 {
+                    debug!("no subscription for response: {:?}", resp.event);
+                }
+                for entry in dispatched.lagged.into_iter().chain(dispatched.closed) {
+                    for request in self.subscriptions.orphaned(&entry) {
+                        self.service_request(request).await?;
                     }
                 }
             }
@@ -323,11 +1224,41 @@ where
         Ok(())
     }
 
+    /// Hands a message no subscription can take to the raw stream, if there is
+    /// one
+    async fn forward_raw(&mut self, text: String, error: Option<serde_json::Error>) {
+        let sink = match &self.raw {
+            Some(sink) => sink,
+            None => {
+                match error {
+                    Some(e) => error!("undecodable message ({}): {}", e, text),
+                    None => debug!("raw message: {}", text),
+                }
+                return;
+            }
+        };
+
+        let mut push = sink.try_push(RawMessage { text, error });
+        while let Push::Full(msg) = push {
+            sink.ready().await;
+            push = sink.try_push(msg);
+        }
+        if let Push::Closed = push {
+            self.raw = None;
+        }
+    }
+
     async fn handle(&mut self, resp: Message) -> Result<(), ClientError> {
         match resp {
             Message::Text(inner) => self.handle_text(inner).await,
             Message::Ping(inner) => self.handle_ping(inner).await,
-            Message::Pong(_) => Ok(()), // Server is allowed to send unsolicited pongs.
+            Message::Pong(_) => {
+                // Server is allowed to send unsolicited pongs.
+                if self.awaiting_pong {
+                    self.reset_heartbeat();
+                }
+                Ok(())
+            }
             Message::Close(Some(frame)) => Err(ClientError::WsClosed(frame)),
             Message::Close(None) => Err(ClientError::UnexpectedClose),
             Message::Binary(buf) => Err(ClientError::UnexpectedBinary(buf)),
@@ -337,7 +1268,19 @@ where
     /// Processes 1 instruction or 1 incoming websocket message
     #[allow(clippy::single_match)]
     async fn tick(&mut self) -> Result<(), ClientError> {
+        let deadline = self.heartbeat.as_ref().map(|_| self.next_beat);
+        let beat = async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => future::pending().await,
+            }
+        }
+        .fuse();
+        futures_util::pin_mut!(beat);
+
         futures_util::select! {
+            // Send keepalive
+            _ = beat => self.beat().await?,
             // Handle requests
             instruction = self.instructions.select_next_some() => {
                 self.service(instruction).await?;
@@ -345,8 +1288,7 @@ where
             // Handle ws messages
             resp = self.ws.next() => match resp {
                 Some(Ok(resp)) => self.handle(resp).await?,
-                // TODO: Log the error?
-                Some(Err(_)) => {},
+                Some(Err(e)) => return Err(e.into()),
                 None => {
                     return Err(ClientError::UnexpectedClose);
                 },
@@ -394,6 +1336,48 @@ pub enum ClientError {
     /// Something caused the websocket to close
     #[error("WebSocket connection closed unexpectedly")]
     UnexpectedClose,
+
+    /// The server refused a request, with its reason
+    #[error("Request rejected by server: {0}")]
+    Rejected(String),
+
+    /// The server did not reply to a request in time
+    #[error("Timed out waiting for the server to reply")]
+    RequestTimeout,
+
+    /// A config was not sent because its ABI is malformed
+    #[error(transparent)]
+    InvalidAbi(#[from] AbiError),
+
+    /// A config was not sent because its filters can never match
+    #[error(transparent)]
+    InvalidFilter(#[from] FilterError),
+
+    /// The server did not answer a keepalive ping in time
+    #[error("No pong within {0:?}, connection is dead")]
+    PongTimeout(Duration),
+
+    /// The connection failed for good, with the error which ended it. Ends
+    /// every stream and request of the connection.
+    #[error("Connection closed: {0}")]
+    ConnectionClosed(Arc<ClientError>),
+
+    /// Gave up reconnecting after the given number of attempts
+    #[error("Failed to reconnect after {0} attempt(s)")]
+    ReconnectFailed(u32),
+}
+
+impl ClientError {
+    /// Whether the error means the connection is gone and should be reopened
+    fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            ClientError::UnexpectedClose
+                | ClientError::WsClosed(_)
+                | ClientError::TungsteniteError(_)
+                | ClientError::PongTimeout(_)
+        )
+    }
 }
 
 #[cfg(test)]
@@ -401,46 +1385,423 @@ mod tests {
     use super::*;
     use crate::{
         models::{Network, System},
-        ws::models::{WatchConfig, WatchRequest},
+        testing::MockServer,
+        ws::filter::Filter,
     };
-    use std::collections::HashMap;
-    use tokio::fs::read_to_string;
 
-    #[tokio::test]
-    async fn request() {
-        let bc = Blockchain {
+    #[test]
+    fn reconnect_backoff() {
+        let policy = ReconnectPolicy {
+            max_attempts: None,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            multiplier: 2,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(4), Duration::from_millis(800));
+        assert_eq!(policy.backoff(5), Duration::from_secs(1));
+        assert_eq!(policy.backoff(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn pending_matches_echoed_request() {
+        let reply: Reply = serde_json::from_str(
+            r#"{"status":"error","reason":"invalid abi","event":{"categoryCode":"configs","eventCode":"put","config":{"scope":"0xA5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff"}}}"#,
+        )
+        .unwrap();
+
+        let pending = |category, event, key: &str| Pending {
+            category,
+            event,
+            key: Some(key.to_string()),
+            sender: oneshot::channel().0,
+        };
+        let scope = "0xa5e0829caced8ffdd4de3c43696c57f7d7a678ff";
+        assert!(pending(CategoryCode::Configs, EventCode::Put, scope).matches(&reply));
+        assert!(!pending(CategoryCode::Configs, EventCode::Put, "0x1").matches(&reply));
+        assert!(!pending(CategoryCode::Configs, EventCode::Delete, scope).matches(&reply));
+        assert_eq!(reply.reason.as_deref(), Some("invalid abi"));
+    }
+
+    #[test]
+    fn matches_handshake_rejections() {
+        let reply: Reply =
+            serde_json::from_str(r#"{"status":"error","reason":"Invalid API key"}"#).unwrap();
+        let pending = |category| Pending {
+            category,
+            event: EventCode::CheckDappId,
+            key: None,
+            sender: oneshot::channel().0,
+        };
+        assert!(pending(CategoryCode::Initialize).matches(&reply));
+        assert!(!pending(CategoryCode::Configs).matches(&reply));
+
+        let hello: Reply = serde_json::from_str(
+            r#"{"version":0,"serverVersion":"0.127.0","status":"ok","showUX":false,"connectionId":"c"}"#,
+        )
+        .unwrap();
+        assert!(!pending(CategoryCode::Initialize).matches(&hello));
+    }
+
+    fn polygon() -> Blockchain {
+        Blockchain {
             system: System::Ethereum,
             network: Network::Polygon,
+        }
+    }
+
+    fn quickswap() -> WatchConfig {
+        let abi = abi::from_file("examples/quickswap.json").unwrap();
+
+        WatchConfig::new("0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff")
+            .with_abi(abi)
+            .filter(Filter::eq(
+                "contractCall.params.path",
+                "0xC250e9987A032ACAC293d838726C511E6E1C029d",
+            ))
+    }
+
+    #[tokio::test]
+    async fn request() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+        assert_eq!(ws.hello().unwrap().connection_id, "mock-0");
+        assert!(ws.ready());
+
+        let mut stream = ws.subscribe(quickswap()).await.unwrap();
+        server.play("tests/fixtures/quickswap.json").unwrap();
+
+        let codes: Vec<_> = (&mut stream)
+            .take(2)
+            .map(|event| event.unwrap().event.unwrap().event_code)
+            .collect()
+            .await;
+        assert_eq!(codes, vec![EventCode::TxPool, EventCode::TxConfirmed]);
+    }
+
+    #[tokio::test]
+    async fn connect_rejects_invalid_api_key() {
+        let server = MockServer::start().await.unwrap();
+        server.reject_api_key("Invalid API key");
+
+        let err = Ws::connect(server.url(), "bad", polygon())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, ClientError::Rejected(reason) if reason == "Invalid API key"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_surfaces_rejected_config() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+
+        server.reject(CategoryCode::Configs, EventCode::Put, "invalid abi");
+        let err = ws.subscribe(quickswap()).await.unwrap_err();
+        assert!(matches!(err, ClientError::Rejected(reason) if reason == "invalid abi"));
+    }
+
+    #[tokio::test]
+    async fn subscribe_validates_filters() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+
+        let config = quickswap().filter(Filter::eq("status", "mined"));
+        let err = ws.subscribe(config).await.unwrap_err();
+        assert!(matches!(err, ClientError::InvalidFilter(_)), "{:?}", err);
+        assert_eq!(server.received().len(), 1);
+
+        // Params outside the given ABI are left to the server
+        let config = quickswap().filter(Filter::eq("contractCall.params.amountOut", "1"));
+        let _stream = ws.subscribe(config).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribes_global_config() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+
+        let config = WatchConfig::global().filter(Filter::field("value").gt("1000"));
+        let first = ws.subscribe(config).await.unwrap();
+
+        let request = server
+            .wait_for(CategoryCode::Configs, EventCode::Put, 1)
+            .await;
+        assert_eq!(request["config"]["scope"], "global");
+        assert_eq!(request["config"]["watchAddress"], false);
+        assert_eq!(
+            request["config"]["filters"],
+            serde_json::json!([{"value": {"gt": "1000"}}])
+        );
+
+        let second = ws.subscribe(WatchConfig::global()).await.unwrap();
+        drop(first);
+        // instructions run in order, so the first drop is done by now
+        let _tx = ws.watch_transaction("0x01").await.unwrap();
+        server
+            .wait_for(CategoryCode::ActiveTransaction, EventCode::TxSent, 1)
+            .await;
+        assert!(server
+            .received()
+            .iter()
+            .all(|request| request["eventCode"] != "delete"));
+
+        drop(second);
+        let request = server
+            .wait_for(CategoryCode::Configs, EventCode::Delete, 1)
+            .await;
+        assert_eq!(request["config"]["scope"], "global");
+    }
+
+    #[tokio::test]
+    async fn keeps_shared_scope_until_last_stream_drops() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+        let first = ws.subscribe(quickswap()).await.unwrap();
+        let second = ws.subscribe(quickswap()).await.unwrap();
+        let deletes = || {
+            server
+                .received()
+                .iter()
+                .filter(|request| request["eventCode"] == "delete")
+                .count()
+        };
+
+        first.unsubscribe().unwrap();
+        // instructions run in order, so the first unsubscribe is done by now
+        let _tx = ws.watch_transaction("0x01").await.unwrap();
+        server
+            .wait_for(CategoryCode::ActiveTransaction, EventCode::TxSent, 1)
+            .await;
+        assert_eq!(deletes(), 0);
+
+        drop(second);
+        let request = server
+            .wait_for(CategoryCode::Configs, EventCode::Delete, 1)
+            .await;
+        assert_eq!(
+            request["config"]["scope"],
+            "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff"
+        );
+        assert_eq!(deletes(), 1);
+    }
+
+    #[tokio::test]
+    async fn unwatches_account_with_last_stream() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+        let address = "0x21F3bB63e775ccDf0CC04559Be142971D241aB0E";
+        let first = ws.watch_account(address).await.unwrap();
+        let second = ws.watch_account(address.to_lowercase()).await.unwrap();
+
+        ws.unwatch_account(first).await.unwrap();
+        let _tx = ws.watch_transaction("0x01").await.unwrap();
+        server
+            .wait_for(CategoryCode::ActiveTransaction, EventCode::TxSent, 1)
+            .await;
+        assert!(!server
+            .received()
+            .iter()
+            .any(|request| request["eventCode"] == "unwatch"));
+
+        ws.unwatch_account(second).await.unwrap();
+        let request = server
+            .wait_for(CategoryCode::AccountAddress, EventCode::Unwatch, 1)
+            .await;
+        assert_eq!(request["account"]["address"], address.to_lowercase());
+    }
+
+    #[tokio::test]
+    async fn replays_subscriptions_after_disconnect() {
+        let server = MockServer::start().await.unwrap();
+        let config = WsConfig {
+            reconnect: ReconnectPolicy {
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let ws = Ws::connect_with_config(server.url(), "key", polygon(), config)
+            .await
+            .unwrap();
+        let mut stream = ws.subscribe(quickswap()).await.unwrap();
+
+        server.disconnect();
+        server
+            .wait_for(CategoryCode::Initialize, EventCode::CheckDappId, 2)
+            .await;
+        server
+            .wait_for(CategoryCode::Configs, EventCode::Put, 2)
+            .await;
+        assert_eq!(server.connections(), 2);
+
+        server.play("tests/fixtures/quickswap.json").unwrap();
+        assert!(stream.next().await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn ends_stream_when_replayed_config_is_rejected() {
+        let server = MockServer::start().await.unwrap();
+        let config = WsConfig {
+            reconnect: ReconnectPolicy {
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let ws = Ws::connect_with_config(server.url(), "key", polygon(), config)
+            .await
+            .unwrap();
+        let mut stream = ws.subscribe(quickswap()).await.unwrap();
+
+        server.reject(CategoryCode::Configs, EventCode::Put, "over quota");
+        server.disconnect();
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert!(
+            matches!(&err, ClientError::Rejected(reason) if reason == "over quota"),
+            "{:?}",
+            err
+        );
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_pongs_stop() {
+        let server = MockServer::start().await.unwrap();
+        let config = WsConfig {
+            reconnect: ReconnectPolicy {
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            },
+            heartbeat: Some(Heartbeat {
+                interval: Duration::from_millis(20),
+                timeout: Duration::from_millis(50),
+            }),
+            ..Default::default()
         };
-        let ws = Ws::connect("wss://api.blocknative.com/v0", "", bc)
+        let _ws = Ws::connect_with_config(server.url(), "key", polygon(), config)
             .await
             .unwrap();
 
-        let s = read_to_string("examples/quickswap.json").await.unwrap();
-        let abi = serde_json::from_str(&s).unwrap();
+        // answered pings keep the connection open
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(server.connections(), 1);
 
-        let mut filters = HashMap::new();
-        filters.insert(
-            "contractCall.params.path".to_string(),
-            "0xC250e9987A032ACAC293d838726C511E6E1C029d".to_string(),
+        server.freeze();
+        server
+            .wait_for(CategoryCode::Initialize, EventCode::CheckDappId, 2)
+            .await;
+        assert_eq!(server.connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn ends_streams_when_connection_fails() {
+        let server = MockServer::start().await.unwrap();
+        let config = WsConfig {
+            reconnect: ReconnectPolicy::disabled(),
+            ..Default::default()
+        };
+        let ws = Ws::connect_with_config(server.url(), "key", polygon(), config)
+            .await
+            .unwrap();
+        let mut stream = ws.subscribe(quickswap()).await.unwrap();
+        let mut state = ws.watch_state();
+        assert!(!state.borrow().is_closed());
+
+        server.disconnect();
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert!(
+            matches!(&err, ClientError::ConnectionClosed(reason) if reason.is_disconnect()),
+            "{:?}",
+            err
         );
+        assert!(stream.next().await.is_none());
+
+        state.wait_for(ConnectionState::is_closed).await.unwrap();
+        assert!(matches!(
+            ws.state(),
+            ConnectionState::Closed { reason: Some(_) }
+        ));
+        assert!(!ws.ready());
+    }
 
-        let config = WatchConfig {
-            scope: "0xa5E0829CaCEd8fFDD4De3c43696c57F7D7A678ff".to_string(),
-            filters: vec![filters],
-            abi,
-            watch_address: true,
+    #[tokio::test]
+    async fn reports_connection_state() {
+        let server = MockServer::start().await.unwrap();
+        let config = WsConfig {
+            reconnect: ReconnectPolicy {
+                initial_backoff: Duration::from_millis(50),
+                ..Default::default()
+            },
+            ..Default::default()
         };
+        let ws = Ws::connect_with_config(server.url(), "key", polygon(), config)
+            .await
+            .unwrap();
+        assert!(ws.state().is_ready());
+        assert_eq!(ws.connection_id().as_deref(), Some("mock-0"));
 
-        let mut stream = ws.subscribe(config).await.unwrap();
+        let mut state = ws.watch_state();
+        server.disconnect();
+        let reconnecting = state
+            .wait_for(|state| !state.is_ready())
+            .await
+            .unwrap()
+            .clone();
+        assert!(
+            matches!(reconnecting, ConnectionState::Reconnecting { attempt: 1 }),
+            "{:?}",
+            reconnecting
+        );
+        assert!(!ws.ready());
 
-        while let Some(event) = stream.next().await {
-            println!("got event: {:?}", event);
-            let txn = event.event.unwrap().transaction.unwrap();
-            // let ether_tx: ethers::prelude::Transaction = txn.into();
+        state.wait_for(ConnectionState::is_ready).await.unwrap();
+        assert_eq!(ws.connection_id().as_deref(), Some("mock-1"));
+    }
 
-            // println("")
-            break;
-        }
+    #[tokio::test]
+    async fn forwards_undecodable_messages() {
+        let server = MockServer::start().await.unwrap();
+        let ws = Ws::connect(server.url(), "key", polygon()).await.unwrap();
+        let mut raw = ws.raw_messages().unwrap();
+
+        // acknowledgements are not raw messages
+        drop(ws.watch_transaction("0x01").await.unwrap());
+        drop(ws.watch_account("0x02").await.unwrap());
+        server.send("not json");
+        let msg = raw.next().await.unwrap();
+        assert_eq!(msg.text, "not json");
+        assert!(msg.error.unwrap().is_syntax());
+
+        // a contract call the models do not understand
+        let fixture = std::fs::read_to_string("tests/fixtures/quickswap.json").unwrap();
+        let mut event =
+            serde_json::from_str::<Vec<serde_json::Value>>(&fixture).unwrap()[0].clone();
+        event["event"]["contractCall"]["methodName"] = 5.into();
+        server.send(event.to_string());
+        assert!(raw.next().await.unwrap().error.is_some());
+        drop(raw);
+
+        let lenient = Ws::connect_with_config(
+            server.url(),
+            "key",
+            polygon(),
+            WsConfig {
+                decode: Decode::Lenient,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let mut lenient_stream = lenient.subscribe(quickswap()).await.unwrap();
+        server.send(event.to_string());
+        let resp = lenient_stream.next().await.unwrap().unwrap();
+        let event = resp.event.unwrap();
+        assert!(event.contract_call.is_none());
+        assert_eq!(event.extra["contractCall"]["methodName"], 5);
     }
 }